@@ -0,0 +1,239 @@
+//! Finding every structural problem in a text, for `analyze` mode and the
+//! `reportAllErrors` option, and splitting it into top-level forms.
+
+use crate::parinfer::{
+    column_byte_index, get_line_ending, is_close_paren, paren_mode, split_lines,
+};
+use crate::types::*;
+use std::borrow::Cow;
+use unicode_segmentation::UnicodeSegmentation;
+
+pub(crate) fn is_top_level_line(line: &str) -> bool {
+    match line.graphemes(true).next() {
+        None => false,
+        Some(ch) => !is_close_paren(ch) && ch != " " && ch != "\t",
+    }
+}
+
+pub(crate) fn offset_error(mut error: Error, line_offset: LineNumber) -> Error {
+    error.line_no += line_offset;
+    error.input_line_no += line_offset;
+    if let Some(ref mut extra) = error.extra {
+        extra.line_no += line_offset;
+        extra.input_line_no += line_offset;
+    }
+    error
+}
+
+fn next_top_level_line(lines: &[&str], after: LineNumber) -> LineNumber {
+    (after + 1..lines.len())
+        .find(|&i| is_top_level_line(lines[i]))
+        .unwrap_or(lines.len())
+}
+
+pub(crate) fn analysis_options(options: &Options) -> Options {
+    Options {
+        cursor_x: None,
+        cursor_line: None,
+        prev_cursor_x: None,
+        prev_cursor_line: None,
+        prev_text: None,
+        selection_start_line: None,
+        changes: vec![],
+        isolate_forms: false,
+        report_all_errors: false,
+        trim_trailing_whitespace: false,
+        max_blank_lines: None,
+        final_newline: false,
+        explain: false,
+        ..options.clone()
+    }
+}
+
+pub(crate) struct TopLevelChunk {
+    pub(crate) start: LineNumber,
+    pub(crate) end: LineNumber,
+    // Paren mode's error for the chunk, in the chunk's own lines.
+    pub(crate) error: Option<Error>,
+}
+
+fn is_unclosed_quote(error: &Option<Error>) -> bool {
+    matches!(
+        error,
+        Some(Error {
+            name: ErrorName::UnclosedQuote,
+            ..
+        })
+    )
+}
+
+// Splits the lines into top-level forms, each checked on its own with paren
+// mode.  A form ends where the next line starts at column zero, unless that
+// line is inside a string.
+pub(crate) fn top_level_chunks(
+    lines: &[&str],
+    line_ending: &str,
+    options: &Options,
+) -> Vec<TopLevelChunk> {
+    let mut chunks = vec![];
+    let mut start = 0;
+    while start < lines.len() {
+        let mut end = next_top_level_line(lines, start);
+        let mut error = paren_mode(&lines[start..end].join(line_ending), options).error;
+        // The column zero line was inside a string.  Rather than feel for
+        // where the string ends a form at a time, take the rest of the text.
+        if is_unclosed_quote(&error) && end < lines.len() {
+            end = lines.len();
+            error = paren_mode(&lines[start..end].join(line_ending), options).error;
+        }
+
+        // an unclosed string swallows the rest of the text, so resynchronize
+        // after the line where the string started
+        if let Some(Error {
+            name: ErrorName::UnclosedQuote,
+            line_no,
+            ..
+        }) = error
+        {
+            end = next_top_level_line(lines, start + line_no);
+        }
+
+        chunks.push(TopLevelChunk { start, end, error });
+        start = end;
+    }
+    chunks
+}
+
+// The errors after the first in a chunk.  Paren mode stops at an error, so
+// the character it blamed is blanked out and the chunk checked again, which
+// keeps the lists around it intact.  Nothing after an unclosed string can be
+// told apart from the string, so that ends the search.
+fn more_chunk_errors(
+    lines: &[&str],
+    line_ending: &str,
+    options: &Options,
+    first: &Error,
+) -> Vec<Error> {
+    let mut lines: Vec<String> = lines.iter().map(|&line| String::from(line)).collect();
+    let mut errors = vec![];
+    let mut error = first.clone();
+    while error.name != ErrorName::UnclosedQuote {
+        let Some(line) = lines.get_mut(error.line_no) else {
+            break;
+        };
+        let start = column_byte_index(line, error.x);
+        let Some(ch) = line[start..]
+            .graphemes(true)
+            .next()
+            .filter(|ch| ch.trim() != "")
+        else {
+            break;
+        };
+        line.replace_range(start..start + ch.len(), " ");
+        let Some(next) = paren_mode(&lines.join(line_ending), options).error else {
+            break;
+        };
+        // An unmatched closer already points at the opener it doesn't match.
+        let already_reported = next.name == ErrorName::UnclosedParen
+            && error
+                .extra
+                .as_ref()
+                .is_some_and(|extra| (extra.line_no, extra.x) == (next.line_no, next.x));
+        if !already_reported {
+            errors.push(next.clone());
+        }
+        error = next;
+    }
+    errors
+}
+
+/// Finds every structural problem in `text` instead of stopping at the first.
+///
+/// Each top-level form is checked on its own with paren mode, so an error in
+/// one form doesn't hide errors in the forms after it, and checked again
+/// past each error it has.
+pub fn analyze(text: &str, options: &Options) -> Vec<Error> {
+    let lines = split_lines(text);
+    let line_ending = get_line_ending(text);
+    let options = analysis_options(options);
+    let mut errors = vec![];
+    for chunk in top_level_chunks(&lines, line_ending, &options) {
+        let Some(error) = chunk.error else {
+            continue;
+        };
+        let chunk_lines = &lines[chunk.start..chunk.end];
+        let mut chunk_errors = more_chunk_errors(chunk_lines, line_ending, &options, &error);
+        chunk_errors.push(error);
+        chunk_errors.sort_by_key(|error| (error.line_no, error.x));
+        errors.extend(
+            chunk_errors
+                .into_iter()
+                .map(|e| offset_error(e, chunk.start)),
+        );
+    }
+    errors
+}
+
+pub fn analyze_mode<'a>(text: &'a str, options: &Options) -> Answer<'a> {
+    let errors = analyze(text, options);
+    Answer {
+        text: Cow::from(text),
+        success: errors.is_empty(),
+        error: errors.first().cloned(),
+        cursor_x: options.cursor_x,
+        cursor_line: options.cursor_line,
+        tab_stops: vec![],
+        paren_trails: vec![],
+        parens: vec![],
+        errors,
+        outline: None,
+        positions: options.positions.clone(),
+        trace: vec![],
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn analyze_works() {
+    let options: Options = serde_json::from_str("{}").unwrap();
+    let names = |text: &str| -> Vec<(ErrorName, LineNumber, Column)> {
+        analyze(text, &options)
+            .into_iter()
+            .map(|e| (e.name, e.line_no, e.x))
+            .collect()
+    };
+
+    assert_eq!(names("(foo)\n(bar)"), vec![]);
+    assert_eq!(
+        names("(foo))\n(bar\n  (baz\n\n(qux \"a)"),
+        vec![
+            (ErrorName::UnmatchedCloseParen, 0, 5),
+            (ErrorName::UnclosedParen, 1, 0),
+            (ErrorName::UnclosedParen, 2, 2),
+            (ErrorName::UnclosedQuote, 4, 5),
+        ]
+    );
+    assert_eq!(
+        names("(a)) b)\n(c (d] e)"),
+        vec![
+            (ErrorName::UnmatchedCloseParen, 0, 3),
+            (ErrorName::UnmatchedCloseParen, 0, 6),
+            (ErrorName::UnclosedParen, 1, 0),
+            (ErrorName::UnmatchedCloseParen, 1, 5),
+        ]
+    );
+    assert_eq!(
+        names("(a \"b\nc\" d))\n(e\n(f)"),
+        vec![
+            (ErrorName::UnmatchedCloseParen, 1, 5),
+            (ErrorName::UnclosedParen, 2, 0),
+        ]
+    );
+    assert_eq!(
+        names("(foo\n  ; \"bar\n  x)\n(baz]"),
+        vec![
+            (ErrorName::QuoteDanger, 1, 4),
+            (ErrorName::UnmatchedCloseParen, 3, 4),
+        ]
+    );
+}
@@ -0,0 +1,503 @@
+//! Parinfer's annotated-text notation, as used by the test cases.
+//!
+//! ```text
+//! (foo
+//!   (bar|))
+//!   ----
+//! (baz "qux
+//!      ^ error: unclosed-quote
+//! ```
+//!
+//! * `|` is the cursor.
+//! * A line of `-` and `+` under a line marks characters that the edit
+//!   removed and added, so that line holds both the old and the new text.  A
+//!   mark just past the end of the line covers its newline.  Columns count
+//!   from the line without its `|`.
+//! * `^ error: NAME` and `^ prevCursor` point at a character of the line
+//!   above, as it is written.
+//! * `^ parenTrail` and `> tabStops` lines are accepted and ignored.
+
+use crate::types::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mark {
+    Kept,
+    Old,
+    New,
+}
+
+/// An error marked with a `^ error: NAME` line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ErrorMark {
+    pub name: ErrorName,
+    pub line_no: LineNumber,
+    pub x: Column,
+}
+
+/// Text with the cursor, changes and error its annotations describe.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Annotated {
+    pub text: String,
+    pub cursor_x: Option<Column>,
+    pub cursor_line: Option<LineNumber>,
+    pub prev_cursor_x: Option<Column>,
+    pub prev_cursor_line: Option<LineNumber>,
+    pub changes: Vec<Change>,
+    pub error: Option<ErrorMark>,
+}
+
+impl Annotated {
+    /// The text and error of an answer, with its cursor.
+    pub fn from_answer(answer: &Answer) -> Annotated {
+        Annotated {
+            text: answer.text.to_string(),
+            cursor_x: answer.cursor_x,
+            cursor_line: answer.cursor_line,
+            error: answer.error.as_ref().map(|e| ErrorMark {
+                name: e.name,
+                line_no: e.line_no,
+                x: e.x,
+            }),
+            ..Annotated::default()
+        }
+    }
+}
+
+enum Caret {
+    Error(ErrorName),
+    PrevCursor,
+    Ignored,
+}
+
+/// One line as written: its characters without the cursor, the mark of each
+/// one plus its newline, and where its cursor and carets are.
+struct Line {
+    chars: Vec<char>,
+    marks: Vec<Mark>,
+    cursor: Option<usize>,
+    carets: Vec<(usize, Caret)>,
+}
+
+fn is_marker_line(line: &str) -> bool {
+    line.contains(['+', '-']) && line.chars().all(|c| c == ' ' || c == '+' || c == '-')
+}
+
+fn parse_caret_line(line: &str) -> Option<Result<(usize, Caret), String>> {
+    let column = line.find(|c| c != ' ')?;
+    let rest = &line[column..];
+    if !rest.starts_with(['^', '>']) {
+        return None;
+    }
+    let label = rest.trim_start_matches(['^', '>', ' ']).trim_end();
+    let caret = if let Some(name) = label.strip_prefix("error: ") {
+        match name.parse() {
+            Ok(name) => Caret::Error(name),
+            Err(e) => return Some(Err(e)),
+        }
+    } else if label == "prevCursor" {
+        Caret::PrevCursor
+    } else if label == "parenTrail" || label == "tabStops" {
+        Caret::Ignored
+    } else {
+        return None;
+    };
+    Some(Ok((column, caret)))
+}
+
+fn parse_lines(input: &str) -> Result<Vec<Line>, String> {
+    let mut lines: Vec<Line> = vec![];
+    let mut seen_cursor = false;
+    for (i, raw) in input.split('\n').enumerate() {
+        if let Some(line) = lines.last_mut() {
+            if is_marker_line(raw) {
+                for (column, c) in raw.chars().enumerate() {
+                    let mark = match c {
+                        '-' => Mark::Old,
+                        '+' => Mark::New,
+                        _ => continue,
+                    };
+                    match line.marks.get_mut(column) {
+                        Some(m) => *m = mark,
+                        None => return Err(format!("line {}: mark past the end of the line", i + 1)),
+                    }
+                }
+                continue;
+            }
+            if let Some(caret) = parse_caret_line(raw) {
+                let (column, caret) = caret.map_err(|e| format!("line {}: {}", i + 1, e))?;
+                line.carets.push((column, caret));
+                continue;
+            }
+        }
+
+        let mut chars = raw.chars().collect::<Vec<char>>();
+        let cursor = chars.iter().position(|&c| c == '|');
+        if let Some(cursor) = cursor {
+            if seen_cursor || chars[cursor + 1..].contains(&'|') {
+                return Err(format!("line {}: more than one cursor", i + 1));
+            }
+            seen_cursor = true;
+            chars.remove(cursor);
+        }
+        let marks = vec![Mark::Kept; chars.len() + 1];
+        lines.push(Line {
+            chars,
+            marks,
+            cursor,
+            carets: vec![],
+        });
+    }
+    if let Some(line) = lines.last() {
+        if line.marks[line.chars.len()] != Mark::Kept {
+            return Err(String::from("the last line has no newline to mark"));
+        }
+    }
+    Ok(lines)
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+struct Position {
+    line_no: LineNumber,
+    x: Column,
+}
+
+impl Position {
+    fn advance(&mut self, c: char) {
+        if c == '\n' {
+            self.line_no += 1;
+            self.x = 0;
+        } else {
+            self.x += 1;
+        }
+    }
+}
+
+/// Where each character of a line, and its newline, is in the new and old
+/// text.
+struct Positions {
+    new: Vec<Position>,
+    old: Vec<Position>,
+}
+
+struct Walk {
+    text: String,
+    changes: Vec<Change>,
+    positions: Vec<Positions>,
+}
+
+fn walk(lines: &[Line]) -> Walk {
+    let mut text = String::new();
+    let mut changes: Vec<Change> = vec![];
+    let mut in_change = false;
+    let mut positions = vec![];
+    let mut new = Position::default();
+    let mut old = Position::default();
+    for (i, line) in lines.iter().enumerate() {
+        let mut line_positions = Positions {
+            new: vec![],
+            old: vec![],
+        };
+        let last = i + 1 == lines.len();
+        for (j, &mark) in line.marks.iter().enumerate() {
+            line_positions.new.push(new);
+            line_positions.old.push(old);
+            let c = match line.chars.get(j) {
+                Some(&c) => c,
+                None if last => break,
+                None => '\n',
+            };
+            match mark {
+                Mark::Kept => {
+                    in_change = false;
+                    text.push(c);
+                    new.advance(c);
+                    old.advance(c);
+                }
+                Mark::Old => {
+                    let continues = in_change
+                        && changes.last().map(|c| c.new_text.is_empty()).unwrap_or(false);
+                    if !continues {
+                        changes.push(Change {
+                            x: old.x,
+                            line_no: old.line_no,
+                            old_text: String::new(),
+                            new_text: String::new(),
+                            kind: ChangeKind::Edit,
+                            source_x: None,
+                        });
+                    }
+                    in_change = true;
+                    changes.last_mut().unwrap().old_text.push(c);
+                    old.advance(c);
+                }
+                Mark::New => {
+                    if !in_change {
+                        changes.push(Change {
+                            x: old.x,
+                            line_no: old.line_no,
+                            old_text: String::new(),
+                            new_text: String::new(),
+                            kind: ChangeKind::Edit,
+                            source_x: None,
+                        });
+                    }
+                    in_change = true;
+                    changes.last_mut().unwrap().new_text.push(c);
+                    text.push(c);
+                    new.advance(c);
+                }
+            }
+        }
+        positions.push(line_positions);
+    }
+    Walk {
+        text,
+        changes,
+        positions,
+    }
+}
+
+/// Parses annotated text.
+pub fn parse(input: &str) -> Result<Annotated, String> {
+    let lines = parse_lines(input)?;
+    let Walk {
+        text,
+        changes,
+        positions,
+    } = walk(&lines);
+    let mut result = Annotated {
+        text,
+        changes,
+        ..Annotated::default()
+    };
+    for (line, positions) in lines.iter().zip(positions.iter()) {
+        if let Some(cursor) = line.cursor {
+            result.cursor_x = Some(positions.new[cursor].x);
+            result.cursor_line = Some(positions.new[cursor].line_no);
+        }
+        for &(column, ref caret) in &line.carets {
+            // Carets count the cursor, which isn't in `chars`.
+            let index = match line.cursor {
+                Some(cursor) if column > cursor => column - 1,
+                _ => column,
+            };
+            if index > line.chars.len() {
+                return Err(String::from("caret past the end of the line"));
+            }
+            match *caret {
+                Caret::Error(name) => {
+                    let Position { line_no, x } = positions.new[index];
+                    result.error = Some(ErrorMark { name, line_no, x });
+                }
+                Caret::PrevCursor => {
+                    result.prev_cursor_x = Some(positions.old[index].x);
+                    result.prev_cursor_line = Some(positions.old[index].line_no);
+                }
+                Caret::Ignored => (),
+            }
+        }
+    }
+    Ok(result)
+}
+
+// Rebuilds the marked characters from the new text and the changes, which are
+// positioned in the old text.
+fn marked_chars(annotated: &Annotated) -> Vec<(char, Mark)> {
+    let mut changes = annotated.changes.iter().collect::<Vec<_>>();
+    changes.sort_by_key(|c| (c.line_no, c.x));
+    let mut changes = changes.into_iter().peekable();
+    let mut text = annotated.text.chars().peekable();
+    let mut old = Position::default();
+    let mut result = vec![];
+    loop {
+        while let Some(change) = changes.next_if(|c| c.line_no == old.line_no && c.x == old.x) {
+            for c in change.old_text.chars() {
+                result.push((c, Mark::Old));
+                old.advance(c);
+            }
+            for c in change.new_text.chars() {
+                result.push((c, Mark::New));
+                text.next_if_eq(&c);
+            }
+        }
+        match text.next() {
+            Some(c) => {
+                result.push((c, Mark::Kept));
+                old.advance(c);
+            }
+            None => break,
+        }
+    }
+    result
+}
+
+fn caret_line(column: usize, label: &str) -> String {
+    format!("{}^ {}", " ".repeat(column), label)
+}
+
+/// Prints text in annotated notation.
+pub fn print(annotated: &Annotated) -> String {
+    let mut lines = vec![Line {
+        chars: vec![],
+        marks: vec![],
+        cursor: None,
+        carets: vec![],
+    }];
+    for (c, mark) in marked_chars(annotated) {
+        let line = lines.last_mut().unwrap();
+        if c == '\n' {
+            line.marks.push(mark);
+            lines.push(Line {
+                chars: vec![],
+                marks: vec![],
+                cursor: None,
+                carets: vec![],
+            });
+        } else {
+            line.chars.push(c);
+            line.marks.push(mark);
+        }
+    }
+    lines.last_mut().unwrap().marks.push(Mark::Kept);
+    let Walk { positions, .. } = walk(&lines);
+
+    let find = |positions: &[Position], marks: &[Mark], skip: Mark, line_no, x| {
+        (0..marks.len()).find(|&j| {
+            marks[j] != skip && positions[j] == Position { line_no, x }
+        })
+    };
+
+    let mut out = vec![];
+    for (line, positions) in lines.iter().zip(positions.iter()) {
+        let cursor = match (annotated.cursor_line, annotated.cursor_x) {
+            (Some(line_no), Some(x)) => find(&positions.new, &line.marks, Mark::Old, line_no, x),
+            _ => None,
+        };
+        // Carets count the cursor, which comes before the character it's at.
+        let column = |index: usize| match cursor {
+            Some(cursor) if cursor <= index => index + 1,
+            _ => index,
+        };
+
+        let mut text = line.chars.iter().collect::<String>();
+        if let Some(cursor) = cursor {
+            let byte = text.char_indices().nth(cursor).map(|(b, _)| b).unwrap_or(text.len());
+            text.insert(byte, '|');
+        }
+        out.push(text);
+
+        let markers = line
+            .marks
+            .iter()
+            .map(|mark| match mark {
+                Mark::Kept => ' ',
+                Mark::Old => '-',
+                Mark::New => '+',
+            })
+            .collect::<String>();
+        if markers.contains(['+', '-']) {
+            out.push(markers.trim_end().to_string());
+        }
+
+        if let (Some(line_no), Some(x)) = (annotated.prev_cursor_line, annotated.prev_cursor_x) {
+            if let Some(index) = find(&positions.old, &line.marks, Mark::New, line_no, x) {
+                out.push(caret_line(column(index), "prevCursor"));
+            }
+        }
+        if let Some(ref error) = annotated.error {
+            if let Some(index) = find(&positions.new, &line.marks, Mark::Old, error.line_no, error.x) {
+                out.push(caret_line(column(index), &format!("error: {}", error.name)));
+            }
+        }
+    }
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(line_no: LineNumber, x: Column, old_text: &str, new_text: &str) -> Change {
+        Change {
+            line_no,
+            x,
+            old_text: String::from(old_text),
+            new_text: String::from(new_text),
+            kind: ChangeKind::Edit,
+            source_x: None,
+        }
+    }
+
+    fn round_trip(input: &str) -> Annotated {
+        let annotated = parse(input).unwrap();
+        assert_eq!(print(&annotated), input);
+        annotated
+    }
+
+    #[test]
+    fn cursor() {
+        let annotated = round_trip("(foo\n  (bar|))");
+        assert_eq!(annotated.text, "(foo\n  (bar))");
+        assert_eq!(annotated.cursor_line, Some(1));
+        assert_eq!(annotated.cursor_x, Some(6));
+    }
+
+    #[test]
+    fn removed_text() {
+        let annotated = round_trip("(foo\n  (bar|))\n  ----");
+        assert_eq!(annotated.text, "(foo\n  ))");
+        assert_eq!(annotated.cursor_x, Some(2));
+        assert_eq!(annotated.changes, vec![change(1, 2, "(bar", "")]);
+    }
+
+    #[test]
+    fn replaced_text() {
+        let annotated = round_trip("(my-fnfoo (if x\n -----+++\n  y))");
+        assert_eq!(annotated.text, "(foo (if x\n  y))");
+        assert_eq!(annotated.changes, vec![change(0, 1, "my-fn", "foo")]);
+    }
+
+    #[test]
+    fn added_lines() {
+        let annotated = round_trip("(let [a 1]\n  (let [a 1]\n  +++++++++++\n  (foo))\n++++++++\n  (foo))");
+        assert_eq!(annotated.text, "(let [a 1]\n  (let [a 1]\n  (foo))\n  (foo))");
+        assert_eq!(
+            annotated.changes,
+            vec![change(1, 2, "", "(let [a 1]\n  (foo))")]
+        );
+    }
+
+    #[test]
+    fn errors_count_the_cursor() {
+        let annotated = round_trip("(foo [a (|b] c)\n           ^ error: unmatched-close-paren");
+        assert_eq!(
+            annotated.error,
+            Some(ErrorMark {
+                name: ErrorName::UnmatchedCloseParen,
+                line_no: 0,
+                x: 10
+            })
+        );
+    }
+
+    #[test]
+    fn prev_cursor() {
+        let annotated = round_trip("((|((1\n ^ prevCursor\n    2)))");
+        assert_eq!(annotated.prev_cursor_x, Some(1));
+        assert_eq!(annotated.prev_cursor_line, Some(0));
+        assert_eq!(annotated.cursor_x, Some(2));
+    }
+
+    #[test]
+    fn paren_trails_and_tab_stops_are_ignored() {
+        let annotated = parse("(foo\n  [a b]\n      ^ parenTrail\n^ ^    > tabStops\n|").unwrap();
+        assert_eq!(annotated.text, "(foo\n  [a b]\n");
+    }
+
+    #[test]
+    fn bad_annotations_are_errors() {
+        assert!(parse("(a|\n(b|").is_err());
+        assert!(parse("(a)\n ^ error: no-such-error").is_err());
+        assert!(parse("(a)\n    ---").is_err());
+    }
+}
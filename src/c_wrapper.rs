@@ -2,7 +2,6 @@ use super::*;
 use libc::c_char;
 use std::cell::RefCell;
 use std::ffi::{CStr, CString};
-use std::panic;
 use types::*;
 
 /// On unix, Vim loads and unloads the library for every call. On Mac, and
@@ -153,20 +152,21 @@ unsafe fn unwrap_c_pointers(json: *const c_char) -> Result<CString, Error> {
 
 thread_local!(static BUFFER: RefCell<Option<CString>> = const { RefCell::new(None) });
 
+/// # Safety
+///
+/// `json` must be a valid pointer to a NUL-terminated string.  The returned
+/// pointer is valid until the next call on the same thread.
 #[cfg(not(target_arch = "wasm32"))]
 #[no_mangle]
 pub unsafe extern "C" fn run_parinfer(json: *const c_char) -> *const c_char {
     reference_hack::initialize();
-    let output = match panic::catch_unwind(|| unwrap_c_pointers(json)) {
+    let input = CStr::from_ptr(json).to_string_lossy();
+    let output = match common_wrapper::catch_panic(Some(&input), || unwrap_c_pointers(json)) {
         Ok(Ok(result)) => result,
-        Ok(Err(e)) => {
+        Ok(Err(e)) | Err(e) => {
             let out = serde_json::to_string(&Answer::from(e)).unwrap();
             CString::new(out).unwrap()
         }
-        Err(_) => {
-            let out = common_wrapper::panic_result();
-            CString::new(out).unwrap()
-        }
     };
 
     BUFFER.with(|buffer| {
@@ -42,6 +42,8 @@ pub fn compute_text_changes<'a>(prev_text: &'a str, text: &'a str) -> Vec<Change
             line_no,
             old_text: String::from(&prev_text[start_prev..end_prev]),
             new_text: String::from(&text[start_text..end_text]),
+            kind: ChangeKind::Edit,
+            source_x: None,
         }]
     } else {
         vec![]
@@ -57,7 +59,9 @@ fn compute_text_changes_works() {
             x: 2,
             line_no: 0,
             old_text: String::from("l"),
-            new_text: String::from("x")
+            new_text: String::from("x"),
+            kind: ChangeKind::Edit,
+            source_x: None,
         }],
         compute_text_changes("hello", "hexlo")
     );
@@ -66,7 +70,9 @@ fn compute_text_changes_works() {
             x: 0,
             line_no: 1,
             old_text: String::from("l"),
-            new_text: String::from("x")
+            new_text: String::from("x"),
+            kind: ChangeKind::Edit,
+            source_x: None,
         }],
         compute_text_changes("he\nllo", "he\nxlo")
     );
@@ -75,7 +81,9 @@ fn compute_text_changes_works() {
             x: 4,
             line_no: 0,
             old_text: String::from(""),
-            new_text: String::from("l")
+            new_text: String::from("l"),
+            kind: ChangeKind::Edit,
+            source_x: None,
         }],
         compute_text_changes("hello", "helllo")
     );
@@ -84,7 +92,9 @@ fn compute_text_changes_works() {
             x: 4,
             line_no: 0,
             old_text: String::from("l"),
-            new_text: String::from("")
+            new_text: String::from(""),
+            kind: ChangeKind::Edit,
+            source_x: None,
         }],
         compute_text_changes("helllo", "hello")
     );
@@ -0,0 +1,118 @@
+use crate::parinfer;
+use crate::types::*;
+
+/// The first line that differs between `before` and `after`, if any.
+pub fn first_changed_line(before: &str, after: &str) -> Option<LineNumber> {
+    let mut before_lines = before.split('\n');
+    let mut after_lines = after.split('\n');
+    let mut line_no = 0;
+    loop {
+        match (before_lines.next(), after_lines.next()) {
+            (None, None) => return None,
+            (a, b) if a != b => return Some(line_no),
+            _ => line_no += 1,
+        }
+    }
+}
+
+/// Why a text failed `--check`.
+pub enum Finding {
+    Failed { mode: &'static str, error: Error },
+    Changed { mode: &'static str, line_no: LineNumber, text: String },
+}
+
+impl Finding {
+    /// Describes the finding in `file:line: message` form.
+    pub fn describe(&self, filename: &str) -> String {
+        match *self {
+            Finding::Failed { mode, ref error } => format!(
+                "{}:{}:{}: {} mode failed: {}\n",
+                filename,
+                error.line_no + 1,
+                error.x + 1,
+                mode,
+                error.message
+            ),
+            Finding::Changed { mode, line_no, .. } => format!(
+                "{}:{}: {} mode would change this line\n",
+                filename,
+                line_no + 1,
+                mode
+            ),
+        }
+    }
+}
+
+/// Runs each of `modes` over the request's text, returning the first one
+/// that would change it or fails.
+pub fn check(request: &Request, modes: &[&'static str]) -> Option<Finding> {
+    for &mode in modes {
+        let request = Request {
+            mode: String::from(mode),
+            text: request.text.clone(),
+            options: request.options.clone(),
+        };
+        let answer = parinfer::process(&request);
+        if let Some(error) = answer.error {
+            return Some(Finding::Failed { mode, error });
+        }
+        if let Some(line_no) = first_changed_line(&request.text, &answer.text) {
+            return Some(Finding::Changed { mode, line_no, text: answer.text.into_owned() });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(text: &str) -> Request {
+        Request {
+            mode: String::from("smart"),
+            text: String::from(text),
+            options: serde_json::from_str("{}").unwrap(),
+        }
+    }
+
+    fn describe(request: &Request, modes: &[&'static str]) -> Option<String> {
+        check(request, modes).map(|finding| finding.describe("a.clj"))
+    }
+
+    #[test]
+    fn first_changed_line_works() {
+        assert_eq!(first_changed_line("a\nb\nc", "a\nb\nc"), None);
+        assert_eq!(first_changed_line("a\nb\nc", "a\nB\nc"), Some(1));
+        assert_eq!(first_changed_line("a\nb", "a\nb\nc"), Some(2));
+        assert_eq!(first_changed_line("a\r\nb", "a\nb"), Some(0));
+    }
+
+    #[test]
+    fn consistent_text_passes() {
+        assert_eq!(describe(&request("(foo\n  bar)\n"), &["indent", "paren"]), None);
+    }
+
+    #[test]
+    fn reports_the_first_mode_that_would_change_the_text() {
+        assert_eq!(
+            describe(&request("(foo\n  bar\n)"), &["indent", "paren"]),
+            Some(String::from("a.clj:2: indent mode would change this line\n"))
+        );
+        assert_eq!(
+            describe(&request("(foo\nbar)"), &["indent", "paren"]),
+            Some(String::from("a.clj:1: indent mode would change this line\n"))
+        );
+        assert_eq!(
+            describe(&request("(foo\nbar)"), &["paren"]),
+            Some(String::from("a.clj:2: paren mode would change this line\n"))
+        );
+    }
+
+    #[test]
+    fn reports_failures() {
+        assert_eq!(
+            describe(&request("(foo \"bar"), &["paren"]),
+            Some(String::from("a.clj:1:6: paren mode failed: String is missing a closing quote.\n"))
+        );
+    }
+}
@@ -1,32 +1,34 @@
+use crate::annotated;
+use crate::config;
+use crate::languages::{self, parse_language, LanguageFeatures, EXTENSIONS};
+use crate::paredit;
+use crate::tab_stops;
 use crate::types;
 use crate::types::*;
+use std::collections::BTreeMap;
 use std::env;
+use std::fs;
 use std::io;
 use std::io::Read;
+use std::path::Path;
 
 pub enum InputType {
+    Annotated,
     Json,
     Kakoune,
     Text,
 }
 
 pub enum OutputType {
+    Annotated,
+    Diagnostics,
+    Diff,
     Json,
     Kakoune,
+    Sarif,
     Text,
 }
 
-enum Language {
-    Clojure,
-    Guile,
-    Hy,
-    Janet,
-    Lisp,
-    Picolisp,
-    Racket,
-    Scheme,
-}
-
 pub struct Options {
     matches: getopts::Matches,
 }
@@ -71,131 +73,162 @@ const HY_BRACKET_STRINGS_OPTION : YesNoDefaultOption = YesNoDefaultOption {
     name: "hy-bracket-strings",
     description: "recognize #[hy-style[ bracket strings ]hy-style]```",
 };
+const FINAL_NEWLINE_OPTION: YesNoDefaultOption = YesNoDefaultOption {
+    name: "final-newline",
+    description: "end the text with a newline",
+};
+const PRESERVE_TABS_OPTION: YesNoDefaultOption = YesNoDefaultOption {
+    name: "preserve-tabs",
+    description: "keep tabs in code instead of expanding them",
+};
+const TRIM_TRAILING_WHITESPACE_OPTION: YesNoDefaultOption = YesNoDefaultOption {
+    name: "trim-trailing-whitespace",
+    description: "trim whitespace at the ends of lines, outside strings",
+};
 
 fn options() -> getopts::Options {
     let mut options = getopts::Options::new();
+    options.optflag(
+        "",
+        "check",
+        "report inputs that indent and paren mode (or just -m MODE) would change, without rewriting them",
+    );
     options.optopt("", "comment-char", "(default: ';')", "CC");
     options.optopt("", "string-delimiters", "(default: '\"')", "DELIM");
+    options.optopt(
+        "",
+        "cursor",
+        "the cursor position, for smart mode",
+        "LINE:COL",
+    );
+    options.optopt(
+        "",
+        "document",
+        "only process the Lisp code blocks of a 'markdown' or 'org' document (default: from the file's extension)",
+        "FMT",
+    );
+    options.optflag(
+        "",
+        "explain",
+        "report why each paren and indentation changed (on stderr, or as `trace` in JSON)",
+    );
+    options.optmulti(
+        "",
+        "extension",
+        "only process files with this extension when searching directories (default: all supported languages)",
+        "EXT",
+    );
+    FINAL_NEWLINE_OPTION.add(&mut options);
     options.optflag("h", "help", "show this help message");
+    options.optflag(
+        "",
+        "in-place",
+        "rewrite FILEs instead of printing the result",
+    );
     options.optopt(
         "",
         "input-format",
-        "'json', 'text' (default: 'text')",
+        "'annotated', 'json', 'text' (default: 'text')",
         "FMT",
     );
     GUILE_BLOCK_COMMENTS_OPTION.add(&mut options);
     HY_BRACKET_STRINGS_OPTION.add(&mut options);
     JANET_LONG_STRINGS_OPTION.add(&mut options);
+    options.optflag(
+        "",
+        "isolate-forms",
+        "leave top-level forms with errors untouched and correct the rest",
+    );
     options.optopt(
         "l",
         "language",
-        "'clojure', 'guile', 'hy', 'janet', 'lisp', 'racket', 'scheme' (default: 'clojure')",
+        "'clojure', 'elisp', 'guile', 'hy', 'janet', 'lisp', 'racket', 'scheme' (default: 'clojure')",
         "LANG",
     );
     LISP_BLOCK_COMMENTS_OPTION.add(&mut options);
     LISP_VLINE_SYMBOLS_OPTION.add(&mut options);
+    options.optopt(
+        "",
+        "max-blank-lines",
+        "collapse runs of blank lines between top-level forms to at most N",
+        "N",
+    );
     options.optopt(
         "m",
         "mode",
-        "parinfer mode (indent, paren, or smart) (default: smart)",
+        "parinfer mode (indent, paren, smart, analyze, outline or format), or a structural edit like slurp-forward (default: smart)",
         "MODE",
     );
     options.optopt(
         "",
         "output-format",
-        "'json', 'kakoune', 'text' (default: 'text')",
+        "'annotated', 'diagnostics', 'diff', 'json', 'kakoune', 'sarif', 'text' (default: 'text')",
         "FMT",
     );
+    options.optopt(
+        "",
+        "prev-cursor",
+        "the cursor position before the edit",
+        "LINE:COL",
+    );
+    options.optopt(
+        "",
+        "prev-text",
+        "the text before the edit, so smart mode can see what changed",
+        "FILE",
+    );
+    PRESERVE_TABS_OPTION.add(&mut options);
+    options.optopt(
+        "",
+        "replay",
+        "run the requests in a session log again and report the first answer that differs",
+        "LOG",
+    );
     SCHEME_SEXP_COMMENTS.add(&mut options);
+    options.optopt(
+        "",
+        "selection-start",
+        "the first line of the selection",
+        "LINE",
+    );
+    options.optopt(
+        "",
+        "tab-width",
+        "expand tabs in code to the next multiple of N columns (default: two spaces each)",
+        "N",
+    );
+    TRIM_TRAILING_WHITESPACE_OPTION.add(&mut options);
     options
 }
 
 pub fn usage() -> String {
-    options().usage("Usage: parinfer-rust [options]")
+    options().usage(
+        "Usage: parinfer-rust [options] [FILE|DIR ...]\n       parinfer-rust --replay LOG",
+    )
 }
 
-fn parse_language(language: Option<String>) -> Language {
-    match language {
-        Some(ref s) if s == "clojure" => Language::Clojure,
-        Some(ref s) if s == "guile" => Language::Guile,
-        Some(ref s) if s == "hy" => Language::Hy,
-        Some(ref s) if s == "janet" => Language::Janet,
-        Some(ref s) if s == "lisp" => Language::Lisp,
-        Some(ref s) if s == "picolisp" => Language::Picolisp,
-        Some(ref s) if s == "racket" => Language::Racket,
-        Some(ref s) if s == "scheme" => Language::Scheme,
-        None => Language::Clojure,
-        // Unknown language.  LanguageFeatures kind of work for most lisps
-        Some(_) => Language::Clojure,
-    }
+fn language_for_path(path: &Path) -> Option<String> {
+    let extension = path.extension()?.to_str()?;
+    languages::language_for_extension(extension).map(String::from)
 }
 
-struct LanguageFeatures {
-    comment_char: char,
-    lisp_vline_symbols: bool,
-    lisp_block_comments: bool,
-    guile_block_comments: bool,
-    scheme_sexp_comments: bool,
-    janet_long_strings: bool,
-    hy_bracket_strings: bool,
+fn document_for_path(path: &Path) -> Option<Document> {
+    match path.extension()?.to_str()? {
+        "md" | "markdown" => Some(Document::Markdown),
+        "org" => Some(Document::Org),
+        _ => None,
+    }
 }
 
-impl LanguageFeatures {
-    fn for_language(language: Language) -> Self {
-        let common = Self {
-            comment_char: ';',
-            lisp_vline_symbols: false,
-            lisp_block_comments: false,
-            guile_block_comments: false,
-            scheme_sexp_comments: false,
-            janet_long_strings: false,
-            hy_bracket_strings: false,
-        };
-        match language {
-            Language::Clojure => Self {
-                ..common
-            },
-            Language::Guile => Self {
-                lisp_vline_symbols: true,
-                lisp_block_comments: true,
-                guile_block_comments: true,
-                scheme_sexp_comments: true,
-                ..common
-            },
-            Language::Hy => Self {
-                hy_bracket_strings: true,
-                ..common
-            },
-            Language::Janet => Self {
-                comment_char: '#',
-                janet_long_strings: true,
-                ..common
-            },
-            Language::Lisp => Self {
-                lisp_vline_symbols: true,
-                lisp_block_comments: true,
-                ..common
-            },
-            Language::Picolisp => Self {
-                comment_char: '#',
-                lisp_vline_symbols: true,
-                lisp_block_comments: true,
-                ..common
-            },
-            Language::Racket => Self {
-                lisp_vline_symbols: true,
-                lisp_block_comments: true,
-                scheme_sexp_comments: true,
-                ..common
-            },
-            Language::Scheme => Self {
-                lisp_vline_symbols: true,
-                lisp_block_comments: true,
-                scheme_sexp_comments: true,
-                ..common
-            },
-        }
+// The language's indentation rules, overridden by any from `.parinfer.toml`.
+fn with_settings_indents(
+    mut indents: BTreeMap<String, Indent>,
+    settings: &config::Settings,
+) -> BTreeMap<String, Indent> {
+    if let Some(ref overrides) = settings.indents {
+        indents.extend(overrides.clone());
     }
+    indents
 }
 
 impl Options {
@@ -216,7 +249,28 @@ impl Options {
             Some(ref s) if s == "i" || s == "indent" => "indent",
             Some(ref s) if s == "p" || s == "paren" => "paren",
             Some(ref s) if s == "s" || s == "smart" => "smart",
-            _ => panic!("invalid mode specified for `-m`"),
+            Some(ref s) if s == "a" || s == "analyze" => "analyze",
+            Some(ref s) if s == "outline" => "outline",
+            Some(ref s) if s == "f" || s == "format" => "format",
+            Some(ref s) => match (s.parse::<paredit::Operation>(), s.parse::<tab_stops::Direction>()) {
+                (Ok(operation), _) => operation.name(),
+                (_, Ok(direction)) => direction.name(),
+                _ => panic!("invalid mode specified for `-m`"),
+            },
+        }
+    }
+
+    pub fn check(&self) -> bool {
+        self.matches.opt_present("check")
+    }
+
+    /// The modes `--check` runs: the one given with `-m`, or both indent and
+    /// paren mode, which together catch indentation and parens that disagree.
+    pub fn check_modes(&self) -> Vec<&'static str> {
+        if self.matches.opt_present("m") {
+            vec![self.mode()]
+        } else {
+            vec!["indent", "paren"]
         }
     }
 
@@ -224,6 +278,7 @@ impl Options {
         match self.matches.opt_str("input-format") {
             None => InputType::Text,
             Some(ref s) if s == "text" => InputType::Text,
+            Some(ref s) if s == "annotated" => InputType::Annotated,
             Some(ref s) if s == "json" => InputType::Json,
             Some(ref s) if s == "kakoune" => InputType::Kakoune,
             Some(ref s) => panic!("unknown input format `{}`", s),
@@ -234,12 +289,25 @@ impl Options {
         match self.matches.opt_str("output-format") {
             None => OutputType::Text,
             Some(ref s) if s == "text" => OutputType::Text,
+            Some(ref s) if s == "annotated" => OutputType::Annotated,
+            Some(ref s) if s == "diagnostics" => OutputType::Diagnostics,
+            Some(ref s) if s == "diff" => OutputType::Diff,
             Some(ref s) if s == "json" => OutputType::Json,
             Some(ref s) if s == "kakoune" => OutputType::Kakoune,
+            Some(ref s) if s == "sarif" => OutputType::Sarif,
             Some(ref s) => panic!("unknown output fomrat `{}`", s),
         }
     }
 
+    fn document(&self) -> Option<Document> {
+        match self.matches.opt_str("document") {
+            None => None,
+            Some(ref s) if s == "markdown" => Some(Document::Markdown),
+            Some(ref s) if s == "org" => Some(Document::Org),
+            Some(ref _s) => panic!("document format must be 'markdown' or 'org'"),
+        }
+    }
+
     fn comment_char(&self) -> Option<char> {
         match self.matches.opt_str("comment-char") {
             None => None,
@@ -248,12 +316,49 @@ impl Options {
         }
     }
 
-    fn string_delimiters(&self) -> Vec<String> {
+    fn string_delimiters(&self) -> Option<Vec<String>> {
         let delims = self.matches.opt_strs("string-delimiters");
         if delims.is_empty() {
-            vec!["\"".to_string()]
+            None
         } else {
-            delims
+            Some(delims)
+        }
+    }
+
+    // Positions on the command line are 1-based, like editors show them.
+    fn position(&self, name: &str) -> Option<(LineNumber, Column)> {
+        let s = self.matches.opt_str(name)?;
+        let parsed = s.split_once(':').and_then(|(line, col)| {
+            let line = line.parse::<LineNumber>().ok()?;
+            let col = col.parse::<Column>().ok()?;
+            Some((line.checked_sub(1)?, col.checked_sub(1)?))
+        });
+        match parsed {
+            Some(position) => Some(position),
+            None => panic!("`--{}` must be LINE:COL, counting from 1", name),
+        }
+    }
+
+    fn selection_start_line(&self) -> Option<LineNumber> {
+        let s = self.matches.opt_str("selection-start")?;
+        match s.parse::<LineNumber>().ok().and_then(|line| line.checked_sub(1)) {
+            Some(line) => Some(line),
+            None => panic!("`--selection-start` must be a LINE, counting from 1"),
+        }
+    }
+
+    fn count(&self, name: &str) -> Option<usize> {
+        let s = self.matches.opt_str(name)?;
+        match s.parse::<usize>() {
+            Ok(count) => Some(count),
+            Err(_) => panic!("`--{}` must be a number", name),
+        }
+    }
+
+    fn prev_text(&self) -> io::Result<Option<String>> {
+        match self.matches.opt_str("prev-text") {
+            None => Ok(None),
+            Some(path) => Ok(Some(fs::read_to_string(path)?)),
         }
     }
 
@@ -291,49 +396,166 @@ impl Options {
         self.invertible_flag("hy-bracket-strings")
     }
 
+    fn text_request(
+        &self,
+        text: String,
+        language: Option<String>,
+        settings: &config::Settings,
+    ) -> io::Result<Request> {
+        let cursor = self.position("cursor");
+        let prev_cursor = self.position("prev-cursor");
+        let language = self
+            .matches
+            .opt_str("language")
+            .or_else(|| settings.language.clone())
+            .or(language);
+        let LanguageFeatures {
+            comment_char,
+            lisp_vline_symbols,
+            lisp_block_comments,
+            guile_block_comments,
+            scheme_sexp_comments,
+            janet_long_strings,
+            hy_bracket_strings,
+            indents,
+        } = LanguageFeatures::for_language(parse_language(language));
+        Ok(Request {
+            mode: String::from(self.mode()),
+            text,
+            options: types::Options {
+                changes: vec![],
+                cursor_x: cursor.map(|(_, x)| x),
+                cursor_line: cursor.map(|(line, _)| line),
+                prev_text: self.prev_text()?,
+                prev_cursor_x: prev_cursor.map(|(_, x)| x),
+                prev_cursor_line: prev_cursor.map(|(line, _)| line),
+                comment_char: self
+                    .comment_char()
+                    .or(settings.comment_char)
+                    .unwrap_or(comment_char),
+                string_delimiters: self
+                    .string_delimiters()
+                    .or_else(|| settings.string_delimiters.clone())
+                    .unwrap_or_else(|| vec!["\"".to_string()]),
+                selection_start_line: self.selection_start_line(),
+                lisp_vline_symbols: self
+                    .lisp_vline_symbols()
+                    .or(settings.lisp_vline_symbols)
+                    .unwrap_or(lisp_vline_symbols),
+                lisp_block_comments: self
+                    .lisp_block_comments()
+                    .or(settings.lisp_block_comments)
+                    .unwrap_or(lisp_block_comments),
+                guile_block_comments: self
+                    .guile_block_comments()
+                    .or(settings.guile_block_comments)
+                    .unwrap_or(guile_block_comments),
+                scheme_sexp_comments: self
+                    .scheme_sexp_comments()
+                    .or(settings.scheme_sexp_comments)
+                    .unwrap_or(scheme_sexp_comments),
+                janet_long_strings: self
+                    .janet_long_strings()
+                    .or(settings.janet_long_strings)
+                    .unwrap_or(janet_long_strings),
+                hy_bracket_strings: self
+                    .hy_bracket_strings()
+                    .or(settings.hy_bracket_strings)
+                    .unwrap_or(hy_bracket_strings),
+                isolate_forms: self.matches.opt_present("isolate-forms"),
+                report_all_errors: false,
+                positions: vec![],
+                indents: with_settings_indents(indents, settings),
+                list_style: settings.list_style.unwrap_or_default(),
+                trim_trailing_whitespace: self
+                    .invertible_flag("trim-trailing-whitespace")
+                    .or(settings.trim_trailing_whitespace)
+                    .unwrap_or(false),
+                tab_width: self.count("tab-width").or(settings.tab_width),
+                preserve_tabs: self
+                    .invertible_flag("preserve-tabs")
+                    .or(settings.preserve_tabs)
+                    .unwrap_or(false),
+                max_blank_lines: self.count("max-blank-lines").or(settings.max_blank_lines),
+                final_newline: self
+                    .invertible_flag("final-newline")
+                    .or(settings.final_newline)
+                    .unwrap_or(false),
+                explain: self.matches.opt_present("explain"),
+                document: self.document(),
+            },
+        })
+    }
+
+    /// The files and directories named on the command line.
+    pub fn paths(&self) -> &[String] {
+        &self.matches.free
+    }
+
+    /// The session log to replay, if that's what to do instead.
+    pub fn replay(&self) -> Option<String> {
+        self.matches.opt_str("replay")
+    }
+
+    pub fn in_place(&self) -> bool {
+        self.matches.opt_present("in-place")
+    }
+
+    /// The extensions of files to process when searching directories.
+    pub fn extensions(&self) -> Vec<String> {
+        let extensions = self.matches.opt_strs("extension");
+        if extensions.is_empty() {
+            EXTENSIONS.iter().map(|(ext, _)| String::from(*ext)).collect()
+        } else {
+            extensions
+                .into_iter()
+                .map(|ext| ext.trim_start_matches('.').to_string())
+                .collect()
+        }
+    }
+
+    /// Reads a file, applying the nearest `.parinfer.toml` and taking its
+    /// language from its extension unless that or `--language` gives one.
+    /// Markdown and Org files only have their code blocks processed.
+    pub fn file_request(&self, path: &Path) -> io::Result<Request> {
+        let text = fs::read_to_string(path)?;
+        let settings = config::settings_for(path)?;
+        let mut request = self.text_request(text, language_for_path(path), &settings)?;
+        request.options.document = request.options.document.or_else(|| document_for_path(path));
+        Ok(request)
+    }
+
     pub fn request(&self, input: &mut dyn Read) -> io::Result<Request> {
         match self.input_type() {
             InputType::Text => {
-                let LanguageFeatures {
-                    comment_char,
-                    lisp_vline_symbols,
-                    lisp_block_comments,
-                    guile_block_comments,
-                    scheme_sexp_comments,
-                    janet_long_strings,
-                    hy_bracket_strings,
-                } = LanguageFeatures::for_language(parse_language(self.matches.opt_str("language")));
                 let mut text = String::new();
                 input.read_to_string(&mut text)?;
-                Ok(Request {
-                    mode: String::from(self.mode()),
-                    text,
-                    options: types::Options {
-                        changes: vec![],
-                        cursor_x: None,
-                        cursor_line: None,
-                        prev_text: None,
-                        prev_cursor_x: None,
-                        prev_cursor_line: None,
-                        comment_char: self.comment_char().unwrap_or(comment_char),
-                        string_delimiters: self.string_delimiters(),
-                        selection_start_line: None,
-                        lisp_vline_symbols: self.lisp_vline_symbols().unwrap_or(lisp_vline_symbols),
-                        lisp_block_comments: self
-                            .lisp_block_comments()
-                            .unwrap_or(lisp_block_comments),
-                        guile_block_comments: self
-                            .guile_block_comments()
-                            .unwrap_or(guile_block_comments),
-                        scheme_sexp_comments: self
-                            .scheme_sexp_comments()
-                            .unwrap_or(scheme_sexp_comments),
-                        janet_long_strings: self.janet_long_strings().unwrap_or(janet_long_strings),
-                        hy_bracket_strings: self.hy_bracket_strings().unwrap_or(hy_bracket_strings),
-                    },
-                })
+                self.text_request(text, None, &config::Settings::default())
+            }
+            InputType::Annotated => {
+                let mut input_text = String::new();
+                input.read_to_string(&mut input_text)?;
+                let annotated = annotated::parse(&input_text)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let mut request =
+                    self.text_request(annotated.text, None, &config::Settings::default())?;
+                let options = &mut request.options;
+                options.cursor_x = annotated.cursor_x.or(options.cursor_x);
+                options.cursor_line = annotated.cursor_line.or(options.cursor_line);
+                options.prev_cursor_x = annotated.prev_cursor_x.or(options.prev_cursor_x);
+                options.prev_cursor_line = annotated.prev_cursor_line.or(options.prev_cursor_line);
+                options.changes = annotated.changes;
+                Ok(request)
             }
             InputType::Kakoune => {
+                let settings = match env::var("kak_buffile") {
+                    Ok(ref buffile) if !buffile.is_empty() => config::settings_for(Path::new(buffile))?,
+                    _ => config::Settings::default(),
+                };
+                let language = settings
+                    .language
+                    .clone()
+                    .or_else(|| env::var("kak_opt_filetype").ok());
                 let LanguageFeatures {
                     comment_char,
                     lisp_vline_symbols,
@@ -342,7 +564,9 @@ impl Options {
                     scheme_sexp_comments,
                     janet_long_strings,
                     hy_bracket_strings,
-                } = LanguageFeatures::for_language(parse_language(env::var("kak_opt_filetype").ok()));
+                    indents,
+                } = LanguageFeatures::for_language(parse_language(language));
+                let indents = with_settings_indents(indents, &settings);
                 Ok(Request {
                     mode: String::from(self.mode()),
                     text: env::var("kak_selection").unwrap(),
@@ -361,15 +585,33 @@ impl Options {
                         prev_cursor_line: env::var("kak_opt_parinfer_previous_cursor_line")
                             .map(|s| s.parse::<LineNumber>().unwrap() - 1)
                             .ok(),
-                        comment_char: self.comment_char().unwrap_or(comment_char),
-                        string_delimiters: self.string_delimiters(),
+                        comment_char: self
+                            .comment_char()
+                            .or(settings.comment_char)
+                            .unwrap_or(comment_char),
+                        string_delimiters: self
+                            .string_delimiters()
+                            .or(settings.string_delimiters)
+                            .unwrap_or_else(|| vec!["\"".to_string()]),
                         selection_start_line: None,
-                        lisp_vline_symbols,
-                        lisp_block_comments,
-                        guile_block_comments,
-                        scheme_sexp_comments,
-                        janet_long_strings,
-                        hy_bracket_strings,
+                        lisp_vline_symbols: settings.lisp_vline_symbols.unwrap_or(lisp_vline_symbols),
+                        lisp_block_comments: settings.lisp_block_comments.unwrap_or(lisp_block_comments),
+                        guile_block_comments: settings.guile_block_comments.unwrap_or(guile_block_comments),
+                        scheme_sexp_comments: settings.scheme_sexp_comments.unwrap_or(scheme_sexp_comments),
+                        janet_long_strings: settings.janet_long_strings.unwrap_or(janet_long_strings),
+                        hy_bracket_strings: settings.hy_bracket_strings.unwrap_or(hy_bracket_strings),
+                        isolate_forms: false,
+                        report_all_errors: false,
+                        positions: vec![],
+                        indents,
+                        list_style: settings.list_style.unwrap_or_default(),
+                        trim_trailing_whitespace: settings.trim_trailing_whitespace.unwrap_or(false),
+                        tab_width: settings.tab_width,
+                        preserve_tabs: settings.preserve_tabs.unwrap_or(false),
+                        max_blank_lines: settings.max_blank_lines,
+                        final_newline: settings.final_newline.unwrap_or(false),
+                        explain: false,
+                        document: None,
                     },
                 })
             }
@@ -439,6 +681,165 @@ mod tests {
         );
     }
 
+    #[test]
+    fn whitespace_options() {
+        let defaults = for_args(&[]).options;
+        assert!(!defaults.trim_trailing_whitespace);
+        assert_eq!(defaults.tab_width, None);
+        assert_eq!(defaults.max_blank_lines, None);
+        let options = for_args(&[
+            "--trim-trailing-whitespace",
+            "--tab-width=4",
+            "--preserve-tabs",
+            "--max-blank-lines=1",
+            "--final-newline",
+        ])
+        .options;
+        assert!(options.trim_trailing_whitespace);
+        assert_eq!(options.tab_width, Some(4));
+        assert!(options.preserve_tabs);
+        assert_eq!(options.max_blank_lines, Some(1));
+        assert!(options.final_newline);
+    }
+
+    #[test]
+    fn replay_is_an_option() {
+        let opts = |args: &[&str]| {
+            Options::parse(&args.iter().map(|&s| String::from(s)).collect::<Vec<_>>()).unwrap()
+        };
+        assert_eq!(
+            opts(&["--replay", "session.jsonl"]).replay(),
+            Some(String::from("session.jsonl"))
+        );
+        let file_named_replay = opts(&["replay", "foo.clj"]);
+        assert_eq!(file_named_replay.replay(), None);
+        assert_eq!(file_named_replay.paths(), ["replay", "foo.clj"]);
+    }
+
+    #[test]
+    fn explain() {
+        assert!(!for_args(&[]).options.explain);
+        assert!(for_args(&["--explain"]).options.explain);
+    }
+
+    #[test]
+    fn language_is_inferred_from_the_file_extension() {
+        assert_eq!(language_for_path(Path::new("src/foo.cljs")), Some(String::from("clojure")));
+        assert_eq!(language_for_path(Path::new("foo.janet")), Some(String::from("janet")));
+        assert_eq!(language_for_path(Path::new("foo.rkt")), Some(String::from("racket")));
+        assert_eq!(language_for_path(Path::new("init.el")), Some(String::from("elisp")));
+        assert_eq!(language_for_path(Path::new("foo.txt")), None);
+        assert_eq!(language_for_path(Path::new("Makefile")), None);
+    }
+
+    #[test]
+    fn document_is_inferred_from_the_file_extension() {
+        assert_eq!(document_for_path(Path::new("README.md")), Some(Document::Markdown));
+        assert_eq!(document_for_path(Path::new("notes.org")), Some(Document::Org));
+        assert_eq!(document_for_path(Path::new("foo.clj")), None);
+        assert_eq!(for_args(&[]).options.document, None);
+        assert_eq!(for_args(&["--document=org"]).options.document, Some(Document::Org));
+    }
+
+    #[test]
+    fn file_request_applies_parinfer_toml() {
+        let dir = env::temp_dir().join(format!("parinfer-cli-config-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(
+            dir.join(config::FILENAME),
+            "[[files]]\nglob = \"*.fnl\"\nlanguage = \"janet\"\nhy-bracket-strings = true\n",
+        )
+        .unwrap();
+        fs::write(dir.join("src/a.fnl"), "(foo)").unwrap();
+        fs::write(dir.join("src/b.scm"), "(foo)").unwrap();
+        let opts = |args: &[&str]| {
+            Options::parse(&args.iter().map(|&s| String::from(s)).collect::<Vec<_>>()).unwrap()
+        };
+
+        let fnl = opts(&[]).file_request(&dir.join("src/a.fnl")).unwrap();
+        assert_eq!(fnl.options.comment_char, '#');
+        assert!(fnl.options.janet_long_strings);
+        assert!(fnl.options.hy_bracket_strings);
+
+        let scm = opts(&[]).file_request(&dir.join("src/b.scm")).unwrap();
+        assert!(scm.options.scheme_sexp_comments);
+        assert!(!scm.options.hy_bracket_strings);
+
+        let overridden = opts(&["--language=clojure", "--no-hy-bracket-strings"])
+            .file_request(&dir.join("src/a.fnl"))
+            .unwrap();
+        assert_eq!(overridden.options.comment_char, ';');
+        assert!(!overridden.options.hy_bracket_strings);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cursor_options_are_one_based() {
+        let request = for_args(&["--cursor=2:5", "--prev-cursor", "1:1", "--selection-start=3"]);
+        assert_eq!(request.options.cursor_line, Some(1));
+        assert_eq!(request.options.cursor_x, Some(4));
+        assert_eq!(request.options.prev_cursor_line, Some(0));
+        assert_eq!(request.options.prev_cursor_x, Some(0));
+        assert_eq!(request.options.selection_start_line, Some(2));
+        assert_eq!(for_args(&[]).options.cursor_x, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "LINE:COL")]
+    fn cursor_must_be_a_position() {
+        for_args(&["--cursor=0:1"]);
+    }
+
+    #[test]
+    fn prev_text_is_read_from_a_file() {
+        let path = env::temp_dir().join(format!("parinfer-prev-text-{}", std::process::id()));
+        fs::write(&path, "(foo)").unwrap();
+        let request = for_args(&["--prev-text", path.to_str().unwrap()]);
+        assert_eq!(request.options.prev_text, Some(String::from("(foo)")));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn annotated_input() {
+        let args = ["--input-format=annotated".to_string()];
+        let request = Options::parse(&args)
+            .unwrap()
+            .request(&mut "(foo\n  (bar|))\n  ----".as_bytes())
+            .unwrap();
+        assert_eq!(request.text, "(foo\n  ))");
+        assert_eq!(request.options.cursor_line, Some(1));
+        assert_eq!(request.options.cursor_x, Some(2));
+        assert_eq!(request.options.changes.len(), 1);
+    }
+
+    #[test]
+    fn extensions() {
+        let opts = |args: &[&str]| {
+            Options::parse(&args.iter().map(|&s| String::from(s)).collect::<Vec<_>>()).unwrap()
+        };
+        assert!(opts(&[]).extensions().contains(&String::from("scm")));
+        assert_eq!(
+            opts(&["--extension=.fnl", "--extension", "lsp"]).extensions(),
+            vec![String::from("fnl"), String::from("lsp")]
+        );
+    }
+
+    #[test]
+    fn check_modes() {
+        let opts = |args: &[&str]| {
+            Options::parse(&args.iter().map(|&s| String::from(s)).collect::<Vec<_>>()).unwrap()
+        };
+        assert_eq!(opts(&["--check"]).check_modes(), vec!["indent", "paren"]);
+        assert_eq!(opts(&["--check", "-m", "paren"]).check_modes(), vec!["paren"]);
+    }
+
+    #[test]
+    fn isolate_forms() {
+        assert!(!for_args(&[]).options.isolate_forms);
+        assert!(for_args(&["--isolate-forms"]).options.isolate_forms);
+    }
+
     #[test]
     fn lisp_block_comments() {
         assert!(!for_args(&[]).options.lisp_block_comments);
@@ -1,31 +1,187 @@
 use super::*;
-use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
 use types::*;
 
+/// When set, the request that made the plugin panic is written to a file in
+/// this directory, named in the error, so it can be attached to a bug report.
+#[cfg(not(target_arch = "wasm32"))]
+pub const CRASH_DIR_ENV: &str = "PARINFER_RUST_CRASH_DIR";
+
 pub fn internal_run(json_str: &str) -> Result<String, Error> {
     let request: Request = serde_json::from_str(json_str)?;
     let answer = parinfer::process(&request);
+    #[cfg(not(target_arch = "wasm32"))]
+    session::record(&request, &answer);
     Ok(serde_json::to_string(&answer)?)
 }
 
-pub fn panic_result() -> String {
-    let answer = Answer {
-        text: Cow::from(""),
-        success: false,
-        error: Some(Error {
-            name: ErrorName::Panic,
-            message: String::from("plugin panicked!"),
-            x: 0,
-            line_no: 0,
-            input_x: 0,
-            input_line_no: 0,
-        }),
-        cursor_x: None,
-        cursor_line: None,
-        tab_stops: vec![],
-        paren_trails: vec![],
-        parens: vec![],
+thread_local! {
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+    static LAST_PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+static INSTALL_HOOK: Once = Once::new();
+
+fn describe(info: &panic::PanicHookInfo) -> String {
+    let payload = info.payload();
+    let message = if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "Box<dyn Any>"
     };
+    match info.location() {
+        Some(location) => format!(
+            "plugin panicked at {}:{}:{}: {}",
+            location.file(),
+            location.line(),
+            location.column(),
+            message
+        ),
+        None => format!("plugin panicked: {}", message),
+    }
+}
+
+// The hook only captures panics we are about to catch; anything else goes to
+// whatever hook was there before, so the host's own panics look the same.
+fn install_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(|c| c.get()) {
+                LAST_PANIC.with(|p| *p.borrow_mut() = Some(describe(info)));
+            } else {
+                previous(info);
+            }
+        }));
+    });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn crash_dir() -> Option<PathBuf> {
+    std::env::var_os(CRASH_DIR_ENV).map(PathBuf::from)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn crash_dir() -> Option<PathBuf> {
+    None
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_crash_file(dir: &Path, request: &str) -> Option<PathBuf> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let path = dir.join(format!(
+        "parinfer-crash-{}-{}.json",
+        std::process::id(),
+        nanos
+    ));
+    std::fs::write(&path, request).ok()?;
+    Some(path)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_crash_file(_dir: &Path, _request: &str) -> Option<PathBuf> {
+    None
+}
+
+/// Runs `f`, turning a panic into a `panic` error that says what panicked
+/// and where.  If `request` is given and `PARINFER_RUST_CRASH_DIR` is set, it
+/// is saved to a crash file named in the message.
+pub fn catch_panic<R>(request: Option<&str>, f: impl FnOnce() -> R) -> Result<R, Error> {
+    catch_panic_saving_to(crash_dir().as_deref(), request, f)
+}
+
+// Like `catch_panic`, saving the request in `crash_dir`.  Panics caught
+// inside `f` leave the hook still catching for this call.
+fn catch_panic_saving_to<R>(
+    crash_dir: Option<&Path>,
+    request: Option<&str>,
+    f: impl FnOnce() -> R,
+) -> Result<R, Error> {
+    install_hook();
+    let was_catching = CATCHING.with(|c| c.replace(true));
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(f));
+    CATCHING.with(|c| c.set(was_catching));
+    result.map_err(|_| {
+        let mut message = LAST_PANIC
+            .with(|p| p.borrow_mut().take())
+            .unwrap_or_else(|| String::from("plugin panicked!"));
+        let saved = crash_dir
+            .zip(request)
+            .and_then(|(dir, request)| write_crash_file(dir, request));
+        if let Some(path) = saved {
+            message.push_str(&format!("; request saved to {}", path.display()));
+        }
+        panic_error(message)
+    })
+}
+
+pub fn panic_error(message: String) -> Error {
+    Error {
+        name: ErrorName::Panic,
+        message,
+        x: 0,
+        line_no: 0,
+        input_x: 0,
+        input_line_no: 0,
+        extra: None,
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panics_report_their_message_and_location() {
+        let error = catch_panic(None, || {
+            if true {
+                panic!("oh no: {}", 42);
+            }
+        })
+        .unwrap_err();
+        assert_eq!(error.name, ErrorName::Panic);
+        assert!(error.message.starts_with("plugin panicked at src/common_wrapper.rs:"));
+        assert!(error.message.ends_with(": oh no: 42"));
+    }
+
+    #[test]
+    fn results_pass_through() {
+        assert_eq!(catch_panic(None, || 42).unwrap(), 42);
+    }
+
+    #[test]
+    fn nested_calls_keep_catching() {
+        let error = catch_panic(None, || {
+            assert_eq!(catch_panic(None, || 42).unwrap(), 42);
+            assert!(catch_panic(None, || panic!("inner")).is_err());
+            panic!("outer");
+        })
+        .unwrap_err();
+        assert!(error.message.starts_with("plugin panicked at src/common_wrapper.rs:"));
+        assert!(error.message.ends_with(": outer"));
+    }
+
+    #[test]
+    fn crash_files_are_opt_in() {
+        let dir = std::env::temp_dir().join(format!("parinfer-crash-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let request = Some("{\"mode\":\"smart\"}");
+        let error = catch_panic_saving_to(Some(&dir), request, || panic!("boom")).unwrap_err();
+        let (_, path) = error.message.split_once("; request saved to ").unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "{\"mode\":\"smart\"}");
 
-    serde_json::to_string(&answer).unwrap()
+        let error = catch_panic_saving_to(None, request, || panic!("boom")).unwrap_err();
+        assert!(!error.message.contains("request saved"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
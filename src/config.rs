@@ -0,0 +1,291 @@
+//! Project configuration in `.parinfer.toml`.
+//!
+//! ```toml
+//! [[files]]
+//! glob = "*.fnl"
+//! language = "clojure"
+//! comment-char = ";"
+//! string-delimiters = ["\""]
+//! lisp-vline-symbols = false
+//! list-style = "one-space"
+//! trim-trailing-whitespace = true
+//! max-blank-lines = 1
+//! final-newline = true
+//! indents = { defroutes = "inner", let-flow = { block = 1 } }
+//! ```
+//!
+//! Each `[[files]]` section whose glob matches a file applies to it, later
+//! sections overriding earlier ones.  `indents` add to the language's rules
+//! for `format` mode instead of replacing them.  Globs without a `/` match the file name
+//! anywhere below the directory holding the configuration; others match the
+//! path relative to it.
+
+use crate::types::{Indent, ListStyle};
+use globset::{GlobBuilder, GlobMatcher};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const FILENAME: &str = ".parinfer.toml";
+
+/// Options for the files a section applies to.  Anything not given is left
+/// to the language's defaults.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Settings {
+    pub language: Option<String>,
+    pub comment_char: Option<char>,
+    pub string_delimiters: Option<Vec<String>>,
+    pub lisp_vline_symbols: Option<bool>,
+    pub lisp_block_comments: Option<bool>,
+    pub guile_block_comments: Option<bool>,
+    pub scheme_sexp_comments: Option<bool>,
+    pub janet_long_strings: Option<bool>,
+    pub hy_bracket_strings: Option<bool>,
+    pub indents: Option<BTreeMap<String, Indent>>,
+    pub list_style: Option<ListStyle>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub tab_width: Option<usize>,
+    pub preserve_tabs: Option<bool>,
+    pub max_blank_lines: Option<usize>,
+    pub final_newline: Option<bool>,
+}
+
+impl Settings {
+    fn merge(&mut self, other: &Settings) {
+        fn set<T: Clone>(to: &mut Option<T>, from: &Option<T>) {
+            if from.is_some() {
+                to.clone_from(from);
+            }
+        }
+        set(&mut self.language, &other.language);
+        set(&mut self.comment_char, &other.comment_char);
+        set(&mut self.string_delimiters, &other.string_delimiters);
+        set(&mut self.lisp_vline_symbols, &other.lisp_vline_symbols);
+        set(&mut self.lisp_block_comments, &other.lisp_block_comments);
+        set(&mut self.guile_block_comments, &other.guile_block_comments);
+        set(&mut self.scheme_sexp_comments, &other.scheme_sexp_comments);
+        set(&mut self.janet_long_strings, &other.janet_long_strings);
+        set(&mut self.hy_bracket_strings, &other.hy_bracket_strings);
+        set(&mut self.list_style, &other.list_style);
+        set(
+            &mut self.trim_trailing_whitespace,
+            &other.trim_trailing_whitespace,
+        );
+        set(&mut self.tab_width, &other.tab_width);
+        set(&mut self.preserve_tabs, &other.preserve_tabs);
+        set(&mut self.max_blank_lines, &other.max_blank_lines);
+        set(&mut self.final_newline, &other.final_newline);
+        if let Some(ref indents) = other.indents {
+            self.indents
+                .get_or_insert_with(BTreeMap::new)
+                .extend(indents.clone());
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default)]
+    files: Vec<toml::Table>,
+}
+
+pub struct Config {
+    root: PathBuf,
+    sections: Vec<(GlobMatcher, Settings)>,
+}
+
+fn absolute(path: &Path) -> io::Result<PathBuf> {
+    if path.is_absolute() {
+        Ok(path.to_path_buf())
+    } else {
+        Ok(std::env::current_dir()?.join(path))
+    }
+}
+
+fn invalid_data(path: &Path, message: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), message),
+    )
+}
+
+impl Config {
+    /// Parses the contents of a configuration file found in `root`.
+    pub fn parse(root: &Path, text: &str) -> Result<Config, String> {
+        let file: File = toml::from_str(text).map_err(|e| e.to_string())?;
+        let sections = file
+            .files
+            .into_iter()
+            .map(|mut section| {
+                let glob = match section.remove("glob") {
+                    Some(toml::Value::String(glob)) => glob,
+                    _ => return Err(String::from("each [[files]] section needs a glob string")),
+                };
+                let settings: Settings = toml::Value::Table(section)
+                    .try_into()
+                    .map_err(|e: toml::de::Error| e.to_string())?;
+                let pattern = if glob.contains('/') {
+                    glob.trim_start_matches('/').to_string()
+                } else {
+                    format!("**/{}", glob)
+                };
+                let glob = GlobBuilder::new(&pattern)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| e.to_string())?;
+                Ok((glob.compile_matcher(), settings))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Config {
+            root: root.to_path_buf(),
+            sections,
+        })
+    }
+
+    /// Reads the configuration file at `path`.
+    pub fn load(path: &Path) -> io::Result<Config> {
+        let text = fs::read_to_string(path)?;
+        let root = path.parent().unwrap_or_else(|| Path::new(""));
+        Config::parse(root, &text).map_err(|e| invalid_data(path, e))
+    }
+
+    /// Finds the nearest `.parinfer.toml` in the directory of `path` or one
+    /// of its ancestors, and reads it.
+    pub fn discover(path: &Path) -> io::Result<Option<Config>> {
+        for dir in absolute(path)?.ancestors().skip(1) {
+            let candidate = dir.join(FILENAME);
+            if candidate.is_file() {
+                return Config::load(&candidate).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// The settings of every section matching `path`, merged in order.
+    pub fn settings_for(&self, path: &Path) -> Settings {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let mut settings = Settings::default();
+        for (glob, section) in &self.sections {
+            if glob.is_match(relative) {
+                settings.merge(section);
+            }
+        }
+        settings
+    }
+}
+
+/// The settings `.parinfer.toml` gives for `path`, if there is one.
+pub fn settings_for(path: &Path) -> io::Result<Settings> {
+    let path = absolute(path)?;
+    match Config::discover(&path)? {
+        Some(config) => Ok(config.settings_for(&path)),
+        None => Ok(Settings::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r##"
+        [[files]]
+        glob = "*.fnl"
+        language = "clojure"
+        comment-char = ";"
+
+        [[files]]
+        glob = "scripts/*.fnl"
+        comment-char = "#"
+        string-delimiters = ["\"", "'"]
+
+        [[files]]
+        glob = "*.lisp"
+        lisp-vline-symbols = false
+    "##;
+
+    fn config() -> Config {
+        Config::parse(Path::new("/project"), CONFIG).unwrap()
+    }
+
+    #[test]
+    fn sections_match_by_file_name_anywhere() {
+        let settings = config().settings_for(Path::new("/project/src/deep/a.fnl"));
+        assert_eq!(settings.language, Some(String::from("clojure")));
+        assert_eq!(settings.comment_char, Some(';'));
+        assert_eq!(settings.string_delimiters, None);
+    }
+
+    #[test]
+    fn later_sections_override_earlier_ones() {
+        let settings = config().settings_for(Path::new("/project/scripts/a.fnl"));
+        assert_eq!(settings.language, Some(String::from("clojure")));
+        assert_eq!(settings.comment_char, Some('#'));
+        assert_eq!(
+            settings.string_delimiters,
+            Some(vec![String::from("\""), String::from("'")])
+        );
+    }
+
+    #[test]
+    fn globs_with_a_slash_match_from_the_root() {
+        let settings = config().settings_for(Path::new("/project/src/scripts/a.fnl"));
+        assert_eq!(settings.comment_char, Some(';'));
+    }
+
+    #[test]
+    fn unmatched_files_get_no_settings() {
+        assert_eq!(
+            config().settings_for(Path::new("/project/a.clj")),
+            Settings::default()
+        );
+    }
+
+    #[test]
+    fn dialect_flags() {
+        let settings = config().settings_for(Path::new("/project/a.lisp"));
+        assert_eq!(settings.lisp_vline_symbols, Some(false));
+        assert_eq!(settings.lisp_block_comments, None);
+    }
+
+    #[test]
+    fn indents_accumulate_across_sections() {
+        let config = Config::parse(
+            Path::new("/project"),
+            r#"
+            [[files]]
+            glob = "*.clj"
+            indents = { defroutes = "inner", let-flow = { block = 1 } }
+
+            [[files]]
+            glob = "test/*.clj"
+            indents = { let-flow = { block = 2 } }
+            list-style = "one-space"
+            "#,
+        )
+        .unwrap();
+        let settings = config.settings_for(Path::new("/project/test/a.clj"));
+        let indents = settings.indents.unwrap();
+        assert_eq!(indents.get("defroutes"), Some(&Indent::Inner));
+        assert_eq!(indents.get("let-flow"), Some(&Indent::Block(2)));
+        assert_eq!(settings.list_style, Some(ListStyle::OneSpace));
+    }
+
+    #[test]
+    fn unknown_keys_are_errors() {
+        assert!(Config::parse(Path::new("/"), "[[files]]\nglob = \"*\"\nlanguag = \"x\"\n").is_err());
+    }
+
+    #[test]
+    fn discover_walks_up_from_the_file() {
+        let dir = std::env::temp_dir().join(format!("parinfer-config-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src/nested")).unwrap();
+        fs::write(dir.join(FILENAME), CONFIG).unwrap();
+        let settings = settings_for(&dir.join("src/nested/a.fnl")).unwrap();
+        assert_eq!(settings.language, Some(String::from("clojure")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,385 @@
+use std::ops::Range;
+
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// An edit, with the positions in the old and new lines just before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Edit {
+    op: Op,
+    old: usize,
+    new: usize,
+}
+
+// Myers' O(ND) difference algorithm in linear space: strips the common
+// prefix and suffix, finds the middle of an edit script by searching from
+// both ends at once, and recurses on either side of it.
+fn edits(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let mut result = vec![];
+    diff(a, b, 0, 0, &mut result);
+    result
+}
+
+// Adds the edits from `a` to `b`, which start at `old` and `new` in the
+// whole text.
+fn diff(a: &[&str], b: &[&str], old: usize, new: usize, result: &mut Vec<Edit>) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+    result.extend((0..prefix).map(|i| Edit {
+        op: Op::Equal,
+        old: old + i,
+        new: new + i,
+    }));
+    let (old, new) = (old + prefix, new + prefix);
+
+    match middle(a, b) {
+        Some((x, y)) => {
+            diff(&a[..x], &b[..y], old, new, result);
+            diff(&a[x..], &b[y..], old + x, new + y, result);
+        }
+        None => {
+            result.extend((0..a.len()).map(|i| Edit {
+                op: Op::Delete,
+                old: old + i,
+                new,
+            }));
+            let old = old + a.len();
+            result.extend((0..b.len()).map(|j| Edit {
+                op: Op::Insert,
+                old,
+                new: new + j,
+            }));
+        }
+    }
+
+    let (old, new) = (old + a.len(), new + b.len());
+    result.extend((0..suffix).map(|i| Edit {
+        op: Op::Equal,
+        old: old + i,
+        new: new + i,
+    }));
+}
+
+// Where the forward and backward searches for the shortest edit script
+// from `a` to `b` meet, which splits it into two shorter ones, or `None` if
+// it is only deletions and insertions.  Each search keeps just the furthest
+// reaching path on each diagonal.
+fn middle(a: &[&str], b: &[&str]) -> Option<(usize, usize)> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max_d = (n + m + 1) / 2;
+    let offset = max_d + 1;
+    let mut forward = vec![-1isize; (2 * offset + 1) as usize];
+    let mut backward = forward.clone();
+    forward[(offset + 1) as usize] = 0;
+    backward[(offset + 1) as usize] = 0;
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    // Diagonals which have run off the edge of the grid.
+    let (mut forward_start, mut forward_end) = (0, 0);
+    let (mut backward_start, mut backward_end) = (0, 0);
+
+    for d in 0..max_d {
+        for k in (-d + forward_start..=d - forward_end).step_by(2) {
+            let i = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && forward[i - 1] < forward[i + 1]) {
+                forward[i + 1]
+            } else {
+                forward[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[i] = x;
+            if x > n {
+                forward_end += 2;
+            } else if y > m {
+                forward_start += 2;
+            } else if odd {
+                let j = offset + delta - k;
+                let backward_x = backward.get(j as usize).copied().unwrap_or(-1);
+                if j >= 0 && backward_x != -1 && x >= n - backward_x {
+                    return Some((x as usize, y as usize));
+                }
+            }
+        }
+
+        for k in (-d + backward_start..=d - backward_end).step_by(2) {
+            let i = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && backward[i - 1] < backward[i + 1]) {
+                backward[i + 1]
+            } else {
+                backward[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[i] = x;
+            if x > n {
+                backward_end += 2;
+            } else if y > m {
+                backward_start += 2;
+            } else if !odd {
+                let j = offset + delta - k;
+                let forward_x = forward.get(j as usize).copied().unwrap_or(-1);
+                if j >= 0 && forward_x != -1 && forward_x >= n - x {
+                    let forward_y = forward_x - (j - offset);
+                    return Some((forward_x as usize, forward_y as usize));
+                }
+            }
+        }
+    }
+    None
+}
+
+fn range(start: usize, count: usize) -> String {
+    let start = if count == 0 { start } else { start + 1 };
+    if count == 1 {
+        format!("{}", start)
+    } else {
+        format!("{},{}", start, count)
+    }
+}
+
+fn push_line(out: &mut String, prefix: char, line: &str) {
+    out.push(prefix);
+    out.push_str(line);
+    if !line.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// A unified diff from `old` to `new`, labelled with `filename`, or an
+/// empty string if they are the same.  Lines keep their own endings, so CRLF
+/// text yields a diff that applies cleanly to it.
+pub fn unified(filename: &str, old: &str, new: &str) -> String {
+    let a: Vec<&str> = old.split_inclusive('\n').collect();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let edits = edits(&a, &b);
+
+    // Group changes whose context overlaps into hunks of edit indices.
+    let mut hunks: Vec<(usize, usize)> = vec![];
+    for (i, edit) in edits.iter().enumerate() {
+        if edit.op == Op::Equal {
+            continue;
+        }
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(edits.len());
+        match hunks.last_mut() {
+            Some(hunk) if start <= hunk.1 => hunk.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", filename, filename);
+    for (start, end) in hunks {
+        let hunk = &edits[start..end];
+        let old_count = hunk.iter().filter(|e| e.op != Op::Insert).count();
+        let new_count = hunk.iter().filter(|e| e.op != Op::Delete).count();
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(hunk[0].old, old_count),
+            range(hunk[0].new, new_count)
+        ));
+        for edit in hunk {
+            match edit.op {
+                Op::Equal => push_line(&mut out, ' ', a[edit.old]),
+                Op::Delete => push_line(&mut out, '-', a[edit.old]),
+                Op::Insert => push_line(&mut out, '+', b[edit.new]),
+            }
+        }
+    }
+    out
+}
+
+/// The runs of lines which differ between `old` and `new`, each as the
+/// range of old lines it replaces.  The range is empty where lines were only
+/// added, just before the old line they were added in front of.
+pub fn changed_lines(old: &str, new: &str) -> Vec<Range<usize>> {
+    let a: Vec<&str> = old.split_inclusive('\n').collect();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let mut changes: Vec<Range<usize>> = vec![];
+    let mut in_change = false;
+    for edit in edits(&a, &b) {
+        match (edit.op, changes.last_mut()) {
+            (Op::Equal, _) => in_change = false,
+            (Op::Delete, Some(change)) if in_change => change.end = edit.old + 1,
+            (Op::Insert, Some(_)) if in_change => (),
+            (op, _) => {
+                let end = if op == Op::Delete {
+                    edit.old + 1
+                } else {
+                    edit.old
+                };
+                changes.push(edit.old..end);
+                in_change = true;
+            }
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_text_has_no_diff() {
+        assert_eq!(unified("a.clj", "(foo\n  bar)\n", "(foo\n  bar)\n"), "");
+    }
+
+    #[test]
+    fn changed_line() {
+        assert_eq!(
+            unified("a.clj", "(foo\nbar)\n", "(foo\n bar)\n"),
+            "--- a.clj\n\
+             +++ a.clj\n\
+             @@ -1,2 +1,2 @@\n \
+             (foo\n\
+             -bar)\n\
+             + bar)\n"
+        );
+    }
+
+    #[test]
+    fn distant_changes_get_separate_hunks() {
+        let old = "(a\n1\n2\n3\n4\n5\n6\n7\n8\n(b\n";
+        let new = "(a)\n1\n2\n3\n4\n5\n6\n7\n8\n(b)\n";
+        assert_eq!(
+            unified("a.clj", old, new),
+            "--- a.clj\n\
+             +++ a.clj\n\
+             @@ -1,4 +1,4 @@\n\
+             -(a\n\
+             +(a)\n \
+             1\n \
+             2\n \
+             3\n\
+             @@ -7,4 +7,4 @@\n \
+             6\n \
+             7\n \
+             8\n\
+             -(b\n\
+             +(b)\n"
+        );
+    }
+
+    #[test]
+    fn insertions_and_deletions() {
+        assert_eq!(
+            unified("a.clj", "a\nb\n", "a\nx\nb\ny\n"),
+            "--- a.clj\n+++ a.clj\n@@ -1,2 +1,4 @@\n a\n+x\n b\n+y\n"
+        );
+        assert_eq!(
+            unified("a.clj", "a\n", ""),
+            "--- a.clj\n+++ a.clj\n@@ -1 +0,0 @@\n-a\n"
+        );
+    }
+
+    #[test]
+    fn changed_lines_are_grouped_into_runs() {
+        assert!(changed_lines("a\nb\n", "a\nb\n").is_empty());
+        assert_eq!(
+            changed_lines("(a\nb\nc\nd)\n", "(a\n b\nc\n d)\n"),
+            vec![1..2, 3..4]
+        );
+        assert_eq!(
+            changed_lines("a\n\n\n\nb\nc\n", "a\n\nb\n c\n"),
+            vec![2..4, 5..6]
+        );
+        assert_eq!(changed_lines("a\nb", "a\nx\nb\n"), vec![1..2]);
+        assert_eq!(changed_lines("a\n", "a\n\n"), vec![1..1]);
+    }
+
+    // The length of the longest common subsequence, the slow way.
+    fn lcs(a: &[&str], b: &[&str]) -> usize {
+        let mut row = vec![0; b.len() + 1];
+        for x in a {
+            let mut diagonal = 0;
+            for (j, y) in b.iter().enumerate() {
+                let above = row[j + 1];
+                row[j + 1] = if x == y {
+                    diagonal + 1
+                } else {
+                    above.max(row[j])
+                };
+                diagonal = above;
+            }
+        }
+        row[b.len()]
+    }
+
+    #[test]
+    fn edit_scripts_are_shortest() {
+        let mut seed = 1u32;
+        let mut line = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            ["a", "b", "c"][(seed >> 16) as usize % 3]
+        };
+        for len in 0..200 {
+            let a: Vec<&str> = (0..len % 23).map(|_| line()).collect();
+            let b: Vec<&str> = (0..len % 19).map(|_| line()).collect();
+            let edits = edits(&a, &b);
+            let (mut old, mut new) = (0, 0);
+            for edit in &edits {
+                assert_eq!((edit.old, edit.new), (old, new));
+                match edit.op {
+                    Op::Equal => {
+                        assert_eq!(a[old], b[new]);
+                        old += 1;
+                        new += 1;
+                    }
+                    Op::Delete => old += 1,
+                    Op::Insert => new += 1,
+                }
+            }
+            assert_eq!((old, new), (a.len(), b.len()));
+            let equal = edits.iter().filter(|edit| edit.op == Op::Equal).count();
+            assert_eq!(equal, lcs(&a, &b), "{:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn keeps_crlf_line_endings() {
+        assert_eq!(
+            unified("a.clj", "(foo\r\nbar)\r\n", "(foo\r\n bar)\r\n"),
+            "--- a.clj\n+++ a.clj\n@@ -1,2 +1,2 @@\n (foo\r\n-bar)\r\n+ bar)\r\n"
+        );
+    }
+
+    #[test]
+    fn marks_a_missing_final_newline() {
+        assert_eq!(
+            unified("a.clj", "(foo", "(foo)"),
+            "--- a.clj\n\
+             +++ a.clj\n\
+             @@ -1 +1 @@\n\
+             -(foo\n\
+             \\ No newline at end of file\n\
+             +(foo)\n\
+             \\ No newline at end of file\n"
+        );
+    }
+}
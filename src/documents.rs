@@ -0,0 +1,500 @@
+//! Finding the Lisp code blocks in Markdown and Org documents, and
+//! processing each of them on its own.
+//!
+//! A block is only processed when its language is one parinfer has a preset
+//! for, named by that language's name or file extension, like `clojure` or
+//! `scm`.  Everything else in the document is prose to leave alone.
+
+use crate::analyze::{offset_error, TopLevelChunk};
+use crate::isolate::{chunk_options, chunked_answer, static_paren};
+use crate::languages::{self, Language, LanguageFeatures};
+use crate::parinfer::{self, get_line_ending, indentation, split_lines};
+use crate::types::*;
+use std::borrow::Cow;
+
+/// The lines of a code block, from `start` up to `end`, without its fences.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodeBlock {
+    pub start: LineNumber,
+    pub end: LineNumber,
+    pub language: Language,
+    /// The indentation the block's lines have in the document, which isn't
+    /// part of their code.
+    pub indent: Column,
+}
+
+impl CodeBlock {
+    pub fn contains(&self, line_no: LineNumber) -> bool {
+        self.start <= line_no && line_no < self.end
+    }
+
+    /// How much of `line`'s indentation belongs to the document.
+    pub fn indent_of(&self, line: &str) -> Column {
+        self.indent.min(indentation(line))
+    }
+}
+
+// Languages' other names in info strings and `#+begin_src` lines.
+const ALIASES: &[(&str, &str)] = &[
+    ("clojurescript", "clojure"),
+    ("common-lisp", "lisp"),
+    ("commonlisp", "lisp"),
+    ("emacs-lisp", "elisp"),
+];
+
+fn language_for_tag(tag: &str) -> Option<Language> {
+    let tag = tag.to_lowercase();
+    let name = ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == tag)
+        .map(|(_, name)| *name)
+        .or_else(|| languages::language_for_extension(&tag))
+        .unwrap_or(&tag);
+    Language::from_name(name)
+}
+
+// A Markdown code fence's indentation, character, length and info string.
+fn markdown_fence(line: &str) -> Option<(Column, char, usize, &str)> {
+    let indent = indentation(line);
+    let content = &line[indent..];
+    let ch = content
+        .chars()
+        .next()
+        .filter(|&ch| ch == '`' || ch == '~')?;
+    let length = content.len() - content.trim_start_matches(ch).len();
+    let info = content[length..].trim();
+    if indent > 3 || length < 3 || (ch == '`' && info.contains('`')) {
+        return None;
+    }
+    Some((indent, ch, length, info))
+}
+
+// Blocks fenced with ``` or ~~~.  A block runs to a closing fence at least
+// as long as its opening one, or to the end of the document.  The language is
+// the info string's first word, which Pandoc writes like `{.clojure}`.
+fn markdown_blocks(lines: &[&str]) -> Vec<CodeBlock> {
+    let mut blocks = vec![];
+    let mut line_no = 0;
+    while line_no < lines.len() {
+        let Some((indent, ch, length, info)) = markdown_fence(lines[line_no]) else {
+            line_no += 1;
+            continue;
+        };
+        let start = line_no + 1;
+        let end = (start..lines.len())
+            .find(|&i| match markdown_fence(lines[i]) {
+                Some((_, close, close_length, "")) => close == ch && close_length >= length,
+                _ => false,
+            })
+            .unwrap_or(lines.len());
+        let tag = info
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .trim_start_matches(['{', '.'])
+            .trim_end_matches('}');
+        if let Some(language) = language_for_tag(tag) {
+            blocks.push(CodeBlock {
+                start,
+                end,
+                language,
+                indent,
+            });
+        }
+        line_no = end + 1;
+    }
+    blocks
+}
+
+// The rest of `line` after an Org keyword like `#+begin_src`, in any case.
+fn org_keyword<'a>(line: &'a str, keyword: &str) -> Option<&'a str> {
+    let line = line.trim_start();
+    let (head, rest) = (line.get(..keyword.len())?, &line[keyword.len()..]);
+    let ends = rest.is_empty() || rest.starts_with(char::is_whitespace);
+    (head.eq_ignore_ascii_case(keyword) && ends).then_some(rest)
+}
+
+// `#+begin_src LANGUAGE` blocks, up to their `#+end_src`.  Their lines
+// are usually indented with the block, which Org strips off.
+fn org_blocks(lines: &[&str]) -> Vec<CodeBlock> {
+    let mut blocks = vec![];
+    let mut line_no = 0;
+    while line_no < lines.len() {
+        let Some(rest) = org_keyword(lines[line_no], "#+begin_src") else {
+            line_no += 1;
+            continue;
+        };
+        let start = line_no + 1;
+        let Some(end) =
+            (start..lines.len()).find(|&i| org_keyword(lines[i], "#+end_src").is_some())
+        else {
+            break;
+        };
+        let tag = rest.split_whitespace().next().unwrap_or("");
+        if let Some(language) = language_for_tag(tag) {
+            let indent = lines[start..end]
+                .iter()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| indentation(line))
+                .min()
+                .unwrap_or(0);
+            blocks.push(CodeBlock {
+                start,
+                end,
+                language,
+                indent,
+            });
+        }
+        line_no = end + 1;
+    }
+    blocks
+}
+
+/// The Lisp code blocks among the lines of a document.
+pub fn code_blocks(lines: &[&str], document: Document) -> Vec<CodeBlock> {
+    match document {
+        Document::Markdown => markdown_blocks(lines),
+        Document::Org => org_blocks(lines),
+    }
+}
+
+// The options for a code block: its language's, with the cursor, changes and
+// positions in the block's own text, which starts at its first line and
+// leaves out the document's indentation.
+fn block_options(options: &Options, block: &CodeBlock, indents: &[Column]) -> Options {
+    let chunk = TopLevelChunk {
+        start: block.start,
+        end: block.end,
+        error: None,
+    };
+    let chunk_options = chunk_options(options, &chunk);
+    let in_block = |line_no: LineNumber, x: Column| x.saturating_sub(indents[line_no]);
+    let block_options = Options {
+        cursor_x: chunk_options
+            .cursor_line
+            .zip(chunk_options.cursor_x)
+            .map(|(line_no, x)| in_block(line_no, x)),
+        prev_cursor_x: chunk_options
+            .prev_cursor_line
+            .zip(chunk_options.prev_cursor_x)
+            .map(|(line_no, x)| in_block(line_no, x)),
+        changes: chunk_options
+            .changes
+            .iter()
+            .map(|change| Change {
+                x: in_block(change.line_no, change.x),
+                ..change.clone()
+            })
+            .collect(),
+        positions: chunk_options
+            .positions
+            .iter()
+            .map(|position| Position {
+                x: in_block(position.line_no, position.x),
+                ..*position
+            })
+            .collect(),
+        isolate_forms: options.isolate_forms,
+        report_all_errors: options.report_all_errors,
+        document: None,
+        ..chunk_options.clone()
+    };
+    LanguageFeatures::for_language(block.language).apply(&block_options)
+}
+
+// An error in a code block, moved to where it is in the document.
+fn block_error(mut error: Error, block: &CodeBlock, indents: &[Column]) -> Error {
+    let indent = |line_no: LineNumber| indents.get(line_no).copied().unwrap_or(0);
+    error.x += indent(error.line_no);
+    error.input_x += indent(error.input_line_no);
+    if let Some(ref mut extra) = error.extra {
+        extra.x += indent(extra.line_no);
+        extra.input_x += indent(extra.input_line_no);
+    }
+    offset_error(error, block.start)
+}
+
+/// Runs `mode` over each Lisp code block in a Markdown or Org document on
+/// its own, with its language's options, leaving the prose and the other
+/// blocks alone.  Blocks which fail are left untouched, and their errors are
+/// reported in `errors`.  Every position is in the whole document.
+pub(crate) fn document_mode<'a>(
+    mode: &str,
+    text: &'a str,
+    document: Document,
+    options: &Options,
+) -> Answer<'a> {
+    let lines = split_lines(text);
+    let line_ending = get_line_ending(text);
+
+    let mut output: Vec<String> = lines.iter().map(|line| String::from(*line)).collect();
+    let mut answer = Answer {
+        outline: (mode == "outline").then(|| Outline {
+            forms: vec![],
+            folds: vec![],
+        }),
+        ..chunked_answer(options)
+    };
+
+    for block in code_blocks(&lines, document) {
+        let indents: Vec<Column> = lines[block.start..block.end]
+            .iter()
+            .map(|line| block.indent_of(line))
+            .collect();
+        let block_text = lines[block.start..block.end]
+            .iter()
+            .zip(&indents)
+            .map(|(line, &indent)| &line[indent..])
+            .collect::<Vec<_>>()
+            .join(line_ending);
+        let block_answer =
+            parinfer::run_mode(mode, &block_text, &block_options(options, &block, &indents));
+
+        let mut errors = block_answer.errors;
+        if errors.is_empty() {
+            errors.extend(block_answer.error);
+        }
+        let errors: Vec<Error> = errors
+            .into_iter()
+            .map(|error| block_error(error, &block, &indents))
+            .collect();
+        if !block_answer.success && answer.success {
+            answer.success = false;
+            answer.error = errors.first().cloned();
+        }
+        answer.errors.extend(errors);
+        // A failed block's text is either untouched or, with isolated forms,
+        // processed around the broken ones.  Nothing here adds or removes
+        // lines, but a block which did couldn't be put back line for line.
+        let block_lines = split_lines(&block_answer.text);
+        if block_lines.len() != indents.len() {
+            continue;
+        }
+
+        let to_document =
+            |line_no: LineNumber, x: Column| (line_no + block.start, x + indents[line_no]);
+        for (i, line) in block_lines.iter().enumerate() {
+            let line_no = block.start + i;
+            output[line_no] = format!("{}{}", &lines[line_no][..indents[i]], line);
+        }
+        if options
+            .cursor_line
+            .is_some_and(|line_no| block.contains(line_no))
+        {
+            if let (Some(line_no), Some(x)) = (block_answer.cursor_line, block_answer.cursor_x) {
+                let (line_no, x) = to_document(line_no, x);
+                answer.cursor_line = Some(line_no);
+                answer.cursor_x = Some(x);
+            }
+        }
+        let positions_in_block = options
+            .positions
+            .iter()
+            .enumerate()
+            .filter(|(_, position)| block.contains(position.line_no))
+            .map(|(i, _)| i);
+        for (i, position) in positions_in_block.zip(block_answer.positions) {
+            let (line_no, x) = to_document(position.line_no, position.x);
+            answer.positions[i] = Position { line_no, x };
+        }
+        answer
+            .tab_stops
+            .extend(block_answer.tab_stops.iter().map(|tab_stop| TabStop {
+                ch: static_paren(tab_stop.ch),
+                line_no: tab_stop.line_no + block.start,
+                x: tab_stop.x + indents[tab_stop.line_no],
+                arg_x: tab_stop.arg_x.map(|x| x + indents[tab_stop.line_no]),
+            }));
+        answer.paren_trails.extend(
+            block_answer
+                .paren_trails
+                .into_iter()
+                .map(|trail| ParenTrail {
+                    line_no: trail.line_no + block.start,
+                    start_x: trail.start_x + indents[trail.line_no],
+                    end_x: trail.end_x + indents[trail.line_no],
+                }),
+        );
+        answer
+            .trace
+            .extend(block_answer.trace.into_iter().map(|decision| {
+                let (line_no, x) = to_document(decision.line_no, decision.x);
+                Decision {
+                    line_no,
+                    x,
+                    ..decision
+                }
+            }));
+        if let (Some(outline), Some(block_outline)) =
+            (answer.outline.as_mut(), block_answer.outline)
+        {
+            outline
+                .forms
+                .extend(block_outline.forms.into_iter().map(|form| {
+                    let (line_no, x) = to_document(form.line_no, form.x);
+                    let (end_line_no, end_x) = to_document(form.end_line_no, form.end_x);
+                    OutlineForm {
+                        line_no,
+                        x,
+                        end_line_no,
+                        end_x,
+                        ..form
+                    }
+                }));
+            outline
+                .folds
+                .extend(block_outline.folds.into_iter().map(|fold| {
+                    let (line_no, x) = to_document(fold.line_no, fold.x);
+                    let (end_line_no, end_x) = to_document(fold.end_line_no, fold.end_x);
+                    Fold {
+                        line_no,
+                        x,
+                        end_line_no,
+                        end_x,
+                    }
+                }));
+        }
+    }
+
+    answer.text = Cow::from(output.join(line_ending));
+    answer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(text: &str, document: Document) -> Vec<(LineNumber, LineNumber, Language, Column)> {
+        let lines: Vec<&str> = text.split('\n').collect();
+        code_blocks(&lines, document)
+            .into_iter()
+            .map(|block| (block.start, block.end, block.language, block.indent))
+            .collect()
+    }
+
+    #[test]
+    fn markdown_fences() {
+        let text = "# Title\n\
+                    ```clojure\n(foo)\n```\n\
+                    ```python\nfoo()\n```\n\
+                    ~~~~ scm extra\n(bar)\n~~~\n~~~~\n\
+                    ```\n(baz)\n```\n\
+                    ```{.rkt}\n(qux)\n```\n\
+                    \u{20}\u{20}``` janet\n  (quux)\n  ```\n\
+                    ````Clojure\n```\n````\n\
+                    ```hy\n(unclosed)";
+        assert_eq!(
+            blocks(text, Document::Markdown),
+            vec![
+                (2, 3, Language::Clojure, 0),
+                (8, 10, Language::Scheme, 0),
+                (15, 16, Language::Racket, 0),
+                (18, 19, Language::Janet, 2),
+                (21, 22, Language::Clojure, 0),
+                (24, 25, Language::Hy, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn org_source_blocks() {
+        let text = "* Heading\n\
+                    #+BEGIN_SRC clojure :results silent\n(foo)\n#+END_SRC\n\
+                    #+begin_src python\nfoo()\n#+end_src\n\
+                    \u{20}\u{20}#+begin_src common-lisp\n    (bar\n\n      baz)\n  #+end_src\n\
+                    #+begin_srcfoo\n#+begin_src scheme\n(unclosed)";
+        assert_eq!(
+            blocks(text, Document::Org),
+            vec![(2, 3, Language::Clojure, 0), (8, 11, Language::Lisp, 4)]
+        );
+    }
+
+    #[test]
+    fn documents_only_have_their_code_blocks_processed() {
+        let run = |text: &str, document: Document, cursor: Option<(LineNumber, Column)>| {
+            let mut options: Options = serde_json::from_str("{}").unwrap();
+            options.document = Some(document);
+            options.cursor_line = cursor.map(|(line_no, _)| line_no);
+            options.cursor_x = cursor.map(|(_, x)| x);
+            let request = Request {
+                mode: String::from("indent"),
+                text: String::from(text),
+                options,
+            };
+            let answer = parinfer::process(&request);
+            let errors: Vec<_> = answer
+                .errors
+                .iter()
+                .map(|error| (error.line_no, error.x))
+                .collect();
+            (
+                answer.text.into_owned(),
+                answer.success,
+                errors,
+                answer.cursor_line.zip(answer.cursor_x),
+            )
+        };
+
+        // prose and other languages are left alone
+        assert_eq!(
+            run(
+                "Call (foo\n\n```clojure\n(defn foo [x]\n  x\n```\n\n```python\nfoo(\n```\n",
+                Document::Markdown,
+                Some((4, 3))
+            ),
+            (
+                String::from(
+                    "Call (foo\n\n```clojure\n(defn foo [x]\n  x)\n```\n\n```python\nfoo(\n```\n"
+                ),
+                true,
+                vec![],
+                Some((4, 3)),
+            )
+        );
+        // a block which fails is left alone, with its error in the document
+        assert_eq!(
+            run(
+                "```clojure\n(foo \"bar\n```\n\n```clojure\n(baz\n```",
+                Document::Markdown,
+                None
+            ),
+            (
+                String::from("```clojure\n(foo \"bar\n```\n\n```clojure\n(baz)\n```"),
+                false,
+                vec![(1, 5)],
+                None,
+            )
+        );
+        // Org's indentation isn't part of the code, and the block's language
+        // picks the comment character
+        assert_eq!(
+            run(
+                "* Notes\n  #+begin_src janet\n    (def x\n      # y)\n      1\n  #+end_src",
+                Document::Org,
+                Some((4, 7))
+            ),
+            (
+                String::from(
+                    "* Notes\n  #+begin_src janet\n    (def x\n      # y)\n      1)\n  #+end_src"
+                ),
+                true,
+                vec![],
+                Some((4, 7)),
+            )
+        );
+
+        // with isolated forms, the rest of a failed block is still processed
+        let mut options: Options = serde_json::from_str("{}").unwrap();
+        options.document = Some(Document::Markdown);
+        options.isolate_forms = true;
+        let request = Request {
+            mode: String::from("indent"),
+            text: String::from("```clojure\n(foo \"bar\n\n(baz\n```"),
+            options,
+        };
+        let answer = parinfer::process(&request);
+        assert!(!answer.success);
+        assert_eq!(answer.error.map(|e| (e.line_no, e.x)), Some((1, 5)));
+        assert_eq!(answer.text, "```clojure\n(foo \"bar\n\n(baz)\n```");
+    }
+}
@@ -1,6 +1,8 @@
+use super::format;
 use super::parinfer::rc_process;
+use super::session;
 use emacs::{Env, IntoLisp, Result, Value};
-use types::{Change, Error, Options, Request, SharedRequest, WrappedAnswer};
+use types::{Change, ChangeKind, Error, ListStyle, Options, Request, SharedRequest, WrappedAnswer};
 
 use std::{cell::RefCell, convert::TryFrom, fs::OpenOptions, io::Write, rc::Rc};
 
@@ -90,6 +92,7 @@ type AliasedRequest<'a> = &'a SharedRequest;
 /// ```
 fn execute(request: AliasedRequest) -> Result<WrappedAnswer> {
     let answer = rc_process(&request);
+    session::record(request, &answer);
     let wrapped_answer = unsafe { WrappedAnswer::new(request.clone(), answer) };
     Ok(wrapped_answer)
 }
@@ -126,6 +129,18 @@ fn make_option() -> Result<Options> {
         scheme_sexp_comments: false,
         janet_long_strings: false,
         hy_bracket_strings: false,
+        isolate_forms: false,
+        report_all_errors: false,
+        positions: vec![],
+        indents: format::indents_for("clojure"),
+        list_style: ListStyle::Align,
+        trim_trailing_whitespace: false,
+        tab_width: None,
+        preserve_tabs: false,
+        max_blank_lines: None,
+        final_newline: false,
+        explain: false,
+        document: None,
     })
 }
 
@@ -163,6 +178,18 @@ fn new_options(
         scheme_sexp_comments: false,
         janet_long_strings: false,
         hy_bracket_strings: false,
+        isolate_forms: false,
+        report_all_errors: false,
+        positions: vec![],
+        indents: format::indents_for("clojure"),
+        list_style: ListStyle::Align,
+        trim_trailing_whitespace: false,
+        tab_width: None,
+        preserve_tabs: false,
+        max_blank_lines: None,
+        final_newline: false,
+        explain: false,
+        document: None,
     })
 }
 
@@ -202,6 +229,8 @@ fn new_change(line_number: i64, x: i64, old_text: String, new_text: String) -> R
         line_no,
         old_text,
         new_text,
+        kind: ChangeKind::Edit,
+        source_x: None,
     };
     Ok(change)
 }
@@ -379,6 +408,8 @@ fn get_in_error<'a>(env: &'a Env, error: &Error, key: Option<String>) -> Result<
         "line_no" => to_i64(Some(error.line_no)).into_lisp(env),
         "input_x" => to_i64(Some(error.input_x)).into_lisp(env),
         "input_line_no" => to_i64(Some(error.input_line_no)).into_lisp(env),
+        "extra_x" => to_i64(error.extra.as_ref().map(|e| e.x)).into_lisp(env),
+        "extra_line_no" => to_i64(error.extra.as_ref().map(|e| e.line_no)).into_lisp(env),
         _ => {
             env.message(format!("Key '{}' unsupported", query))?; // Can return an error
             ().into_lisp(env)
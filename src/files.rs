@@ -0,0 +1,194 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+
+fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => extensions.iter().any(|e| e == ext),
+        None => false,
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name.starts_with('.'),
+        None => false,
+    }
+}
+
+// Symlinked directories aren't followed, so a link back up the tree can't
+// loop; symlinked files are processed like any other.
+fn walk(dir: &Path, extensions: &[String], result: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.and_then(|e| Ok((e.path(), e.file_type()?))))
+        .collect::<io::Result<Vec<(PathBuf, fs::FileType)>>>()?;
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (path, file_type) in entries {
+        if is_hidden(&path) {
+            continue;
+        }
+        if file_type.is_dir() {
+            walk(&path, extensions, result)?;
+        } else if has_extension(&path, extensions) && !(file_type.is_symlink() && path.is_dir()) {
+            result.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Expands directories into the files beneath them with one of `extensions`,
+/// skipping hidden entries.  Files named explicitly are always kept.
+pub fn expand(paths: &[String], extensions: &[String]) -> io::Result<Vec<PathBuf>> {
+    let mut result = vec![];
+    for path in paths {
+        let path = PathBuf::from(path);
+        if path.is_dir() {
+            walk(&path, extensions, &mut result)?;
+        } else {
+            result.push(path);
+        }
+    }
+    Ok(result)
+}
+
+/// Replaces the contents of `path` by writing a temporary file next to it
+/// and renaming it over the original, so readers never see a partial file.
+/// The original's permissions are kept, and a symlink is kept by replacing
+/// the file it points to.
+pub fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let path = fs::canonicalize(path)?;
+    let permissions = fs::metadata(&path)?.permissions();
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?
+        .to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.parinfer-{}", name, process::id()));
+    let result = write_synced(&temp, contents)
+        .and_then(|_| fs::set_permissions(&temp, permissions))
+        .and_then(|_| fs::rename(&temp, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+// Writes a file and waits for it to reach the disk, so a crash after it is
+// renamed can't leave an empty file.
+fn write_synced(path: &Path, contents: &str) -> io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages;
+    use std::env;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("parinfer-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn expand_recurses_into_directories_by_extension() {
+        let dir = scratch_dir("expand");
+        fs::create_dir_all(dir.join("src/nested")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        for file in &["src/a.clj", "src/nested/b.scm", "src/c.txt", ".git/d.clj", "e.txt"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let extensions = vec![String::from("clj"), String::from("scm")];
+        let paths = vec![
+            dir.join("src").to_string_lossy().into_owned(),
+            dir.join("e.txt").to_string_lossy().into_owned(),
+        ];
+        assert_eq!(
+            expand(&paths, &extensions).unwrap(),
+            vec![
+                dir.join("src/a.clj"),
+                dir.join("src/nested/b.scm"),
+                dir.join("e.txt"),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expand_finds_every_supported_language_by_default() {
+        let dir = scratch_dir("languages");
+        fs::create_dir_all(dir.join("emacs")).unwrap();
+        for file in &["emacs/init.el", "core.clj", "notes.txt"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let extensions: Vec<String> = languages::EXTENSIONS
+            .iter()
+            .map(|(ext, _)| String::from(*ext))
+            .collect();
+        let paths = vec![dir.to_string_lossy().into_owned()];
+        let mut found = expand(&paths, &extensions).unwrap();
+        found.sort();
+        assert_eq!(found, vec![dir.join("core.clj"), dir.join("emacs/init.el")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn expand_skips_symlinked_directories() {
+        use std::os::unix::fs::symlink;
+
+        let dir = scratch_dir("symlinks");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/a.clj"), "").unwrap();
+        symlink(&dir, dir.join("src/loop")).unwrap();
+        symlink(dir.join("src/a.clj"), dir.join("src/b.clj")).unwrap();
+        let paths = vec![dir.to_string_lossy().into_owned()];
+        assert_eq!(
+            expand(&paths, &[String::from("clj")]).unwrap(),
+            vec![dir.join("src/a.clj"), dir.join("src/b.clj")]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomically_keeps_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = scratch_dir("write-symlink");
+        fs::write(dir.join("a.clj"), "(foo").unwrap();
+        symlink(dir.join("a.clj"), dir.join("link.clj")).unwrap();
+        write_atomically(&dir.join("link.clj"), "(foo)").unwrap();
+        assert!(fs::symlink_metadata(dir.join("link.clj"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(dir.join("a.clj")).unwrap(), "(foo)");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomically_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = scratch_dir("write");
+        let path = dir.join("a.clj");
+        fs::write(&path, "(foo").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o754)).unwrap();
+        write_atomically(&path, "(foo)\r\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "(foo)\r\n");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o754
+        );
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,430 @@
+//! Conventional indentation, for `format` mode: every line reindented from
+//! the paren tree, by rules for each form's head like cljfmt's indents.
+
+use crate::paredit::{Form, List, Source};
+use crate::parinfer;
+use crate::types::*;
+use std::collections::{BTreeMap, HashMap};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+const COMMON_INDENTS: &[(&str, Indent)] = &[
+    ("^def", Indent::Inner),
+    ("^with-", Indent::Block(1)),
+    ("case", Indent::Block(1)),
+    ("cond", Indent::Block(0)),
+    ("do", Indent::Block(0)),
+    ("lambda", Indent::Block(1)),
+    ("let", Indent::Block(1)),
+    ("let*", Indent::Block(1)),
+    ("loop", Indent::Block(1)),
+    ("unless", Indent::Block(1)),
+    ("when", Indent::Block(1)),
+];
+
+const CLOJURE_INDENTS: &[(&str, Indent)] = &[
+    ("binding", Indent::Block(1)),
+    ("catch", Indent::Block(2)),
+    ("comment", Indent::Block(0)),
+    ("cond->", Indent::Block(1)),
+    ("cond->>", Indent::Block(1)),
+    ("condp", Indent::Block(2)),
+    ("doseq", Indent::Block(1)),
+    ("dotimes", Indent::Block(1)),
+    ("extend-protocol", Indent::Block(1)),
+    ("extend-type", Indent::Block(1)),
+    ("finally", Indent::Block(0)),
+    ("fn", Indent::Inner),
+    ("for", Indent::Block(1)),
+    ("if-let", Indent::Block(1)),
+    ("if-some", Indent::Block(1)),
+    ("letfn", Indent::Block(1)),
+    ("ns", Indent::Block(1)),
+    ("reify", Indent::Inner),
+    ("try", Indent::Block(0)),
+    ("when-let", Indent::Block(1)),
+    ("when-not", Indent::Block(1)),
+    ("when-some", Indent::Block(1)),
+];
+
+const LISP_INDENTS: &[(&str, Indent)] = &[
+    ("destructuring-bind", Indent::Block(2)),
+    ("dolist", Indent::Block(1)),
+    ("dotimes", Indent::Block(1)),
+    ("flet", Indent::Block(1)),
+    ("handler-case", Indent::Block(1)),
+    ("labels", Indent::Block(1)),
+    ("multiple-value-bind", Indent::Block(2)),
+    ("progn", Indent::Block(0)),
+    ("unwind-protect", Indent::Block(1)),
+];
+
+const SCHEME_INDENTS: &[(&str, Indent)] = &[
+    ("begin", Indent::Block(0)),
+    ("let-values", Indent::Block(1)),
+    ("letrec", Indent::Block(1)),
+    ("parameterize", Indent::Block(1)),
+    ("syntax-case", Indent::Block(2)),
+    ("syntax-rules", Indent::Block(1)),
+];
+
+const JANET_INDENTS: &[(&str, Indent)] = &[
+    ("each", Indent::Block(2)),
+    ("fn", Indent::Inner),
+    ("for", Indent::Block(3)),
+    ("try", Indent::Block(0)),
+];
+
+const HY_INDENTS: &[(&str, Indent)] = &[
+    ("fn", Indent::Inner),
+    ("for", Indent::Block(1)),
+    ("with", Indent::Block(1)),
+];
+
+const PICOLISP_INDENTS: &[(&str, Indent)] = &[("de", Indent::Inner), ("dm", Indent::Inner)];
+
+/// The indentation rules for a language, by the names the CLI and
+/// `.parinfer.toml` use.  A rule whose name starts with `^` applies to every
+/// head starting with the rest of it.
+pub fn indents_for(language: &str) -> BTreeMap<String, Indent> {
+    let extra = match language {
+        "clojure" => CLOJURE_INDENTS,
+        "hy" => HY_INDENTS,
+        "janet" => JANET_INDENTS,
+        "elisp" | "lisp" => LISP_INDENTS,
+        "picolisp" => PICOLISP_INDENTS,
+        "guile" | "racket" | "scheme" => SCHEME_INDENTS,
+        _ => &[],
+    };
+    COMMON_INDENTS
+        .iter()
+        .chain(extra)
+        .map(|(name, indent)| (String::from(*name), *indent))
+        .collect()
+}
+
+// The rule for a head, ignoring its namespace.  Exact names win over the
+// longest matching prefix.
+fn rule(indents: &BTreeMap<String, Indent>, head: &str) -> Option<Indent> {
+    let name = match head.rsplit_once('/') {
+        Some((_, name)) if !name.is_empty() => name,
+        _ => head,
+    };
+    indents.get(name).copied().or_else(|| {
+        indents
+            .iter()
+            .filter_map(|(key, indent)| Some((key.strip_prefix('^')?, indent)))
+            .filter(|(prefix, _)| name.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, indent)| *indent)
+    })
+}
+
+// The width of `text` at the start of a line, counting tabs the way paren
+// mode expands them.
+fn width(text: &str, tab_width: Option<Column>) -> Column {
+    text.graphemes(true).fold(0, |x, ch| match ch {
+        "\t" => x + parinfer::tab_width_at(x, tab_width),
+        _ => x + UnicodeWidthStr::width(ch),
+    })
+}
+
+struct Formatter<'a, 'b> {
+    source: &'b Source<'a>,
+    options: &'a Options,
+    line_starts: Vec<usize>,
+    // How far each line's indentation has moved so far.
+    deltas: Vec<Delta>,
+    children: HashMap<Option<usize>, Vec<Form<'a, 'b>>>,
+}
+
+impl<'a, 'b> Formatter<'a, 'b> {
+    fn line_no(&self, offset: usize) -> LineNumber {
+        self.line_starts.partition_point(|&start| start <= offset) - 1
+    }
+
+    // The column of `offset` once its line is reindented.
+    fn x(&self, offset: usize) -> Column {
+        let line_no = self.line_no(offset);
+        let line = &self.source.text[self.line_starts[line_no]..offset];
+        let x = width(line, self.options.tab_width);
+        (x as Delta + self.deltas[line_no]) as Column
+    }
+
+    fn children(&mut self, list: Option<&'b List<'a>>) -> &[Form<'a, 'b>] {
+        let source = self.source;
+        self.children
+            .entry(list.map(|list| list.open))
+            .or_insert_with(|| source.children(list))
+    }
+
+    // Whether `list` is commented out by a `#;` before it.
+    fn is_sexp_comment(&mut self, parent: Option<&'b List<'a>>, list: &List<'a>) -> bool {
+        let text = self.source.text;
+        let siblings = self.children(parent);
+        let Some(index) = siblings
+            .iter()
+            .position(|form| form.list.map(|l| l.open) == Some(list.open))
+        else {
+            return false;
+        };
+        text[siblings[index].start..].starts_with("#;")
+            || index
+                .checked_sub(1)
+                .map(|prev| &text[siblings[prev].start..siblings[prev].end] == "#;")
+                .unwrap_or(false)
+    }
+
+    // The indentation of a line whose first character is at `offset`, or
+    // `None` to leave it alone because it starts inside a string or a
+    // comment.
+    fn indent(&mut self, offset: usize) -> Option<Column> {
+        let source = self.source;
+        if source.stringish_lines[self.line_no(offset)] {
+            return None;
+        }
+        let path = source.enclosing(offset);
+        if self.options.scheme_sexp_comments {
+            let parents = std::iter::once(None).chain(path.iter().map(|&list| Some(list)));
+            for (parent, list) in parents.zip(&path) {
+                if self.is_sexp_comment(parent, list) {
+                    return None;
+                }
+            }
+        }
+        let list = path.last().copied();
+        let Some(list) = list else {
+            return Some(0);
+        };
+
+        let open_line = self.line_no(list.open);
+        let open_x = self.x(list.open);
+        if list.open_ch != "(" {
+            return Some(open_x + 1);
+        }
+        let children = self.children(Some(list)).to_vec();
+        let head = children
+            .first()
+            .filter(|head| head.list.is_none())
+            .map(|head| &source.text[head.start..head.end])
+            .filter(|head| source.is_atom(head));
+        // A block breaks its line if one of its first `n` arguments or the
+        // form after them starts on a later line.
+        let is_body = match head.and_then(|head| rule(&self.options.indents, head)) {
+            Some(Indent::Inner) => true,
+            Some(Indent::Block(n)) => children
+                .iter()
+                .take(n + 2)
+                .skip(1)
+                .any(|form| self.line_no(form.start) > open_line),
+            None => false,
+        };
+        if is_body {
+            return Some(open_x + 2);
+        }
+        match (self.options.list_style, head, children.get(1)) {
+            (ListStyle::Align, Some(_), Some(arg)) if self.line_no(arg.start) == open_line => {
+                Some(self.x(arg.start))
+            }
+            _ => Some(open_x + 1),
+        }
+    }
+}
+
+/// Reindents every line of balanced `text` by the rules in `options`.
+/// Returns the new text and the options to run paren mode over it with,
+/// where the cursor and the tracked positions have moved with their lines,
+/// or `None` if `text` isn't balanced.
+pub fn format(text: &str, options: &Options) -> Option<(String, Options)> {
+    let source = Source::parse(text, options).ok()?;
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let lines: Vec<&str> = text.split('\n').collect();
+    let mut formatter = Formatter {
+        source: &source,
+        options,
+        deltas: vec![0; lines.len()],
+        line_starts,
+        children: HashMap::new(),
+    };
+
+    let mut output = vec![];
+    for (line_no, line) in lines.iter().enumerate() {
+        let content = line.trim_start_matches([' ', '\t']);
+        let whitespace = &line[..line.len() - content.len()];
+        let new_indent = if content.trim().is_empty() {
+            None
+        } else {
+            formatter.indent(formatter.line_starts[line_no] + whitespace.len())
+        };
+        match new_indent {
+            Some(new_indent) => {
+                let old_indent = width(whitespace, options.tab_width);
+                formatter.deltas[line_no] = new_indent as Delta - old_indent as Delta;
+                output.push(format!("{}{}", " ".repeat(new_indent), content));
+            }
+            None => output.push(String::from(*line)),
+        }
+    }
+
+    let shift = |line_no: LineNumber, x: Column| match formatter.deltas.get(line_no) {
+        Some(&delta) => (x as Delta + delta).max(0) as Column,
+        None => x,
+    };
+    let cursor_x = match (options.cursor_line, options.cursor_x) {
+        (Some(line_no), Some(x)) => Some(shift(line_no, x)),
+        (_, x) => x,
+    };
+    let positions = options
+        .positions
+        .iter()
+        .map(|position| Position {
+            x: shift(position.line_no, position.x),
+            ..*position
+        })
+        .collect();
+    let options = Options {
+        cursor_x,
+        prev_cursor_x: None,
+        prev_cursor_line: None,
+        prev_text: None,
+        selection_start_line: None,
+        changes: vec![],
+        positions,
+        ..options.clone()
+    };
+    Some((output.join("\n"), options))
+}
+
+/// Reindents every line by the `indents` rules and `list_style` once paren
+/// mode has balanced the text, then runs paren mode again for the trails.
+pub fn format_mode<'a>(text: &'a str, options: &Options) -> Answer<'a> {
+    let answer = parinfer::paren_mode(text, options);
+    if !answer.success {
+        return answer;
+    }
+    let balanced = Options {
+        cursor_x: answer.cursor_x,
+        cursor_line: answer.cursor_line,
+        prev_cursor_x: None,
+        prev_cursor_line: None,
+        prev_text: None,
+        selection_start_line: None,
+        changes: vec![],
+        positions: answer.positions.clone(),
+        ..options.clone()
+    };
+    match format(&answer.text, &balanced) {
+        Some((formatted, options)) => {
+            parinfer::owned_answer(parinfer::paren_mode(&formatted, &options))
+        }
+        None => answer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(text: &str) -> String {
+        let options: Options = serde_json::from_str("{}").unwrap();
+        super::format(text, &options).unwrap().0
+    }
+
+    #[test]
+    fn rules_match_names_namespaces_and_prefixes() {
+        let indents = indents_for("clojure");
+        assert_eq!(rule(&indents, "let"), Some(Indent::Block(1)));
+        assert_eq!(rule(&indents, "clojure.core/let"), Some(Indent::Block(1)));
+        assert_eq!(rule(&indents, "defrecord"), Some(Indent::Inner));
+        assert_eq!(rule(&indents, "with-open"), Some(Indent::Block(1)));
+        assert_eq!(rule(&indents, "/"), None);
+        assert_eq!(rule(&indents, "foo"), None);
+    }
+
+    #[test]
+    fn bodies_get_two_spaces() {
+        assert_eq!(
+            format("(defn foo\n[x]\n        (let [y x]\n(inc y)))"),
+            "(defn foo\n  [x]\n  (let [y x]\n    (inc y)))"
+        );
+        assert_eq!(
+            format("(when x y\nz)\n(when x\ny)"),
+            "(when x y\n      z)\n(when x\n  y)"
+        );
+    }
+
+    #[test]
+    fn other_lists_align_with_their_first_argument() {
+        assert_eq!(
+            format("(foo bar\n baz\n   (qux\n quux))"),
+            "(foo bar\n     baz\n     (qux\n      quux))"
+        );
+        assert_eq!(format("((foo) bar\n  baz)"), "((foo) bar\n baz)");
+        assert_eq!(format("[a\n   b\n {:c\n d}]"), "[a\n b\n {:c\n  d}]");
+    }
+
+    #[test]
+    fn one_space_lists() {
+        let mut options: Options = serde_json::from_str("{}").unwrap();
+        options.list_style = ListStyle::OneSpace;
+        assert_eq!(
+            super::format("(foo bar\n     baz)\n(let [x 1]\nx)", &options)
+                .unwrap()
+                .0,
+            "(foo bar\n baz)\n(let [x 1]\n  x)"
+        );
+    }
+
+    #[test]
+    fn strings_and_blank_lines_are_left_alone() {
+        assert_eq!(
+            format("  (foo \"a\n     b\"\n\n        c)"),
+            "(foo \"a\n     b\"\n\n     c)"
+        );
+    }
+
+    #[test]
+    fn block_comments_are_left_alone() {
+        let mut options: Options = serde_json::from_str("{}").unwrap();
+        options.lisp_block_comments = true;
+        options.scheme_sexp_comments = true;
+        assert_eq!(
+            super::format("(foo #|\n   (bar\n baz) |#\n  qux)", &options)
+                .unwrap()
+                .0,
+            "(foo #|\n   (bar\n baz) |#\n     qux)"
+        );
+        assert_eq!(
+            super::format("(foo #;(bar\n  baz)\n  qux #;\n (a\n b))", &options)
+                .unwrap()
+                .0,
+            "(foo #;(bar\n  baz)\n     qux #;\n     (a\n b))"
+        );
+    }
+
+    #[test]
+    fn tabs_count_as_paren_mode_expands_them() {
+        assert_eq!(width("\t", None), 2);
+        assert_eq!(width("a\t", Some(4)), 4);
+        assert_eq!(format("(foo bar\n\tbaz)"), "(foo bar\n     baz)");
+        let mut options: Options = serde_json::from_str("{}").unwrap();
+        options.cursor_line = Some(1);
+        options.cursor_x = Some(3);
+        options.tab_width = Some(2);
+        let (_, options) = super::format("(let [x 1]\n\tx)", &options).unwrap();
+        assert_eq!(options.cursor_x, Some(3));
+    }
+
+    #[test]
+    fn cursor_and_positions_move_with_their_lines() {
+        let mut options: Options = serde_json::from_str("{}").unwrap();
+        options.cursor_line = Some(1);
+        options.cursor_x = Some(6);
+        options.positions = vec![Position { x: 0, line_no: 1 }];
+        let (_, options) = super::format("(let [x 1]\n     x)", &options).unwrap();
+        assert_eq!(options.cursor_x, Some(3));
+        assert_eq!(options.positions, vec![Position { x: 0, line_no: 1 }]);
+    }
+}
@@ -0,0 +1,251 @@
+//! Processing each top-level form on its own, so that one broken form
+//! doesn't stop the rest of the text from being corrected.
+
+use crate::analyze::{analysis_options, offset_error, top_level_chunks, TopLevelChunk};
+use crate::parinfer::{self, get_line_ending, match_paren, split_lines};
+use crate::types::*;
+use std::borrow::Cow;
+
+fn line_in_chunk(line_no: Option<LineNumber>, chunk: &TopLevelChunk) -> Option<LineNumber> {
+    line_no
+        .filter(|&line_no| chunk.start <= line_no && line_no < chunk.end)
+        .map(|line_no| line_no - chunk.start)
+}
+
+pub(crate) fn chunk_options(options: &Options, chunk: &TopLevelChunk) -> Options {
+    let cursor_line = line_in_chunk(options.cursor_line, chunk);
+    let prev_cursor_line = line_in_chunk(options.prev_cursor_line, chunk);
+    Options {
+        cursor_x: options.cursor_x.filter(|_| cursor_line.is_some()),
+        cursor_line,
+        prev_cursor_x: options.prev_cursor_x.filter(|_| prev_cursor_line.is_some()),
+        prev_cursor_line,
+        prev_text: None,
+        selection_start_line: line_in_chunk(options.selection_start_line, chunk),
+        changes: options
+            .changes
+            .iter()
+            .filter(|change| line_in_chunk(Some(change.line_no), chunk).is_some())
+            .map(|change| Change {
+                line_no: change.line_no - chunk.start,
+                ..change.clone()
+            })
+            .collect(),
+        positions: options
+            .positions
+            .iter()
+            .filter_map(|position| {
+                line_in_chunk(Some(position.line_no), chunk).map(|line_no| Position {
+                    line_no,
+                    ..*position
+                })
+            })
+            .collect(),
+        isolate_forms: false,
+        report_all_errors: false,
+        // Chunks are stitched back together line for line.
+        max_blank_lines: None,
+        final_newline: false,
+        ..options.clone()
+    }
+}
+
+// Tab stops borrow their paren from the text, which here is a temporary chunk.
+pub(crate) fn static_paren(ch: &str) -> &'static str {
+    match_paren(ch).and_then(match_paren).unwrap_or("")
+}
+
+/// Processes each top-level form on its own.  Forms which fail are left
+/// untouched and their errors are reported in `errors`, so one broken form
+/// doesn't stop the rest of the text from being corrected.  As with code
+/// blocks in documents, the answer fails with the first of them.
+pub(crate) fn process_isolated_forms<'a>(
+    mode: &str,
+    text: &'a str,
+    options: &Options,
+) -> Answer<'a> {
+    let lines = split_lines(text);
+    let line_ending = get_line_ending(text);
+    // Smart mode is only smart without a selection, which a chunk may not see.
+    let mode = if mode == "smart" && options.selection_start_line.is_some() {
+        "indent"
+    } else {
+        mode
+    };
+
+    let mut output: Vec<String> = vec![];
+    let mut answer = chunked_answer(options);
+
+    for chunk in top_level_chunks(&lines, line_ending, &analysis_options(options)) {
+        let chunk_text = lines[chunk.start..chunk.end].join(line_ending);
+        let chunk_answer = parinfer::run_mode(mode, &chunk_text, &chunk_options(options, &chunk));
+        if !chunk_answer.success {
+            if let Some(error) = chunk_answer.error {
+                answer.errors.push(offset_error(error, chunk.start));
+            }
+            output.push(chunk_text);
+            continue;
+        }
+        add_chunk_answer(&mut answer, &mut output, options, &chunk, chunk_answer);
+    }
+    if let Some(error) = answer.errors.first() {
+        answer.success = false;
+        answer.error = Some(error.clone());
+    }
+
+    answer.text = Cow::from(output.join(line_ending));
+    answer
+}
+
+#[cfg(test)]
+#[test]
+fn isolated_form_errors_fail_the_answer() {
+    let mut options: Options = serde_json::from_str("{}").unwrap();
+    options.isolate_forms = true;
+    options.report_all_errors = true;
+    let request = Request {
+        mode: String::from("indent"),
+        text: String::from("(a \"b)\n\n(c\n  d\n(e))"),
+        options,
+    };
+    let answer = parinfer::process(&request);
+    assert!(!answer.success);
+    assert_eq!(answer.text, "(a \"b)\n\n(c\n  d)\n(e)");
+    assert_eq!(answer.error.map(|e| e.name), Some(ErrorName::UnclosedQuote));
+    let errors: Vec<(ErrorName, LineNumber)> =
+        answer.errors.iter().map(|e| (e.name, e.line_no)).collect();
+    assert_eq!(
+        errors,
+        vec![
+            (ErrorName::UnclosedQuote, 0),
+            (ErrorName::UnclosedParen, 2),
+            (ErrorName::UnmatchedCloseParen, 4),
+        ]
+    );
+}
+
+// The answer for text processed in chunks, before any chunk is added.
+pub(crate) fn chunked_answer<'a>(options: &Options) -> Answer<'a> {
+    Answer {
+        text: Cow::from(""),
+        success: true,
+        error: None,
+        cursor_x: options.cursor_x,
+        cursor_line: options.cursor_line,
+        tab_stops: vec![],
+        paren_trails: vec![],
+        parens: vec![],
+        errors: vec![],
+        outline: None,
+        positions: options.positions.clone(),
+        trace: vec![],
+    }
+}
+
+// Adds a chunk's successful answer to the whole text's, moving everything in
+// it down to the chunk's lines.
+pub(crate) fn add_chunk_answer(
+    answer: &mut Answer<'_>,
+    output: &mut Vec<String>,
+    options: &Options,
+    chunk: &TopLevelChunk,
+    chunk_answer: Answer<'_>,
+) {
+    let positions_in_chunk = options
+        .positions
+        .iter()
+        .enumerate()
+        .filter(|(_, position)| line_in_chunk(Some(position.line_no), chunk).is_some())
+        .map(|(i, _)| i);
+    if line_in_chunk(options.cursor_line, chunk).is_some() {
+        answer.cursor_x = chunk_answer.cursor_x;
+        answer.cursor_line = chunk_answer
+            .cursor_line
+            .map(|line_no| line_no + chunk.start);
+    }
+    for (i, position) in positions_in_chunk.zip(chunk_answer.positions) {
+        answer.positions[i] = Position {
+            line_no: position.line_no + chunk.start,
+            ..position
+        };
+    }
+    answer
+        .tab_stops
+        .extend(chunk_answer.tab_stops.iter().map(|tab_stop| TabStop {
+            ch: static_paren(tab_stop.ch),
+            line_no: tab_stop.line_no + chunk.start,
+            ..*tab_stop
+        }));
+    answer.paren_trails.extend(
+        chunk_answer
+            .paren_trails
+            .into_iter()
+            .map(|trail| ParenTrail {
+                line_no: trail.line_no + chunk.start,
+                ..trail
+            }),
+    );
+    answer.parens.extend(
+        chunk_answer
+            .parens
+            .iter()
+            .map(|paren| offset_paren(paren, chunk.start)),
+    );
+    answer
+        .trace
+        .extend(chunk_answer.trace.into_iter().map(|decision| Decision {
+            line_no: decision.line_no + chunk.start,
+            ..decision
+        }));
+    output.push(chunk_answer.text.into_owned());
+}
+
+// A paren from a chunk, with its closer and children, moved down to the
+// chunk's lines.
+fn offset_paren(paren: &Paren<'_>, line_offset: LineNumber) -> Paren<'static> {
+    Paren {
+        ch: static_paren(paren.ch),
+        line_no: paren.line_no + line_offset,
+        input_line_no: paren.input_line_no + line_offset,
+        closer: paren.closer.clone().map(|closer| Closer {
+            input_line_no: closer.input_line_no + line_offset,
+            trail: closer.trail.map(|trail| ParenTrail {
+                line_no: trail.line_no + line_offset,
+                ..trail
+            }),
+            ..closer
+        }),
+        children: paren
+            .children
+            .iter()
+            .map(|child| offset_paren(child, line_offset))
+            .collect(),
+        ..*paren
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn chunk_answers_are_moved_to_the_chunks_lines() {
+    let options: Options = serde_json::from_str("{}").unwrap();
+    let chunk = TopLevelChunk {
+        start: 3,
+        end: 5,
+        error: None,
+    };
+    let mut chunk_answer = parinfer::paren_mode("(a\n (b))", &options);
+    chunk_answer.parens = parinfer::paren_tree("(a\n (b))", &options).unwrap().0;
+    let mut answer = chunked_answer(&options);
+    let mut output = vec![];
+    add_chunk_answer(&mut answer, &mut output, &options, &chunk, chunk_answer);
+
+    let paren = &answer.parens[0];
+    assert_eq!((paren.line_no, paren.input_line_no), (3, 3));
+    assert_eq!(
+        paren.closer.as_ref().map(|closer| closer.input_line_no),
+        Some(4)
+    );
+    assert_eq!(paren.children[0].line_no, 4);
+    assert_eq!(answer.paren_trails[0].line_no, 4);
+    assert_eq!(output, vec![String::from("(a\n (b))")]);
+}
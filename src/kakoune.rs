@@ -64,12 +64,11 @@ pub fn fixes<'a>(from: &'a str, to: &'a str) -> Fixes {
         deletions: vec![],
     };
 
-    let mut line: LineNumber = 1;
-    for (a_line, b_line) in from
-        .split('\n')
-        .map(chomp_cr)
-        .zip(to.split('\n').map(chomp_cr))
-    {
+    for (line, (a_line, b_line)) in (1..).zip(
+        from.split('\n')
+            .map(chomp_cr)
+            .zip(to.split('\n').map(chomp_cr)),
+    ) {
         if a_line != b_line {
             result
                 .deletions
@@ -78,7 +77,6 @@ pub fn fixes<'a>(from: &'a str, to: &'a str) -> Fixes {
                 result.insertions.push(Insertion::new(line, 1, b_line));
             }
         }
-        line += 1;
     }
 
     result
@@ -0,0 +1,171 @@
+//! The Lisps parinfer knows: the file extensions they use and the options
+//! each needs.
+
+use crate::format;
+use crate::types::*;
+use std::collections::BTreeMap;
+
+pub const EXTENSIONS: &[(&str, &str)] = &[
+    ("bb", "clojure"),
+    ("clj", "clojure"),
+    ("cljc", "clojure"),
+    ("cljs", "clojure"),
+    ("edn", "clojure"),
+    ("el", "elisp"),
+    ("hy", "hy"),
+    ("janet", "janet"),
+    ("asd", "lisp"),
+    ("cl", "lisp"),
+    ("lisp", "lisp"),
+    ("lsp", "lisp"),
+    ("l", "picolisp"),
+    ("rkt", "racket"),
+    ("scm", "scheme"),
+    ("sld", "scheme"),
+    ("sls", "scheme"),
+    ("ss", "scheme"),
+];
+
+/// The language of files with `extension`.
+pub fn language_for_extension(extension: &str) -> Option<&'static str> {
+    EXTENSIONS
+        .iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, language)| *language)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    Clojure,
+    Elisp,
+    Guile,
+    Hy,
+    Janet,
+    Lisp,
+    Picolisp,
+    Racket,
+    Scheme,
+}
+
+impl Language {
+    pub fn from_name(name: &str) -> Option<Language> {
+        match name {
+            "clojure" => Some(Language::Clojure),
+            "elisp" => Some(Language::Elisp),
+            "guile" => Some(Language::Guile),
+            "hy" => Some(Language::Hy),
+            "janet" => Some(Language::Janet),
+            "lisp" => Some(Language::Lisp),
+            "picolisp" => Some(Language::Picolisp),
+            "racket" => Some(Language::Racket),
+            "scheme" => Some(Language::Scheme),
+            _ => None,
+        }
+    }
+}
+
+/// The named language, or Clojure if there's none or it's unknown, since
+/// its features kind of work for most lisps.
+pub fn parse_language(language: Option<String>) -> Language {
+    language
+        .as_deref()
+        .and_then(Language::from_name)
+        .unwrap_or(Language::Clojure)
+}
+
+pub struct LanguageFeatures {
+    pub comment_char: char,
+    pub lisp_vline_symbols: bool,
+    pub lisp_block_comments: bool,
+    pub guile_block_comments: bool,
+    pub scheme_sexp_comments: bool,
+    pub janet_long_strings: bool,
+    pub hy_bracket_strings: bool,
+    pub indents: BTreeMap<String, Indent>,
+}
+
+impl LanguageFeatures {
+    pub fn for_language(language: Language) -> Self {
+        let common = Self {
+            comment_char: ';',
+            lisp_vline_symbols: false,
+            lisp_block_comments: false,
+            guile_block_comments: false,
+            scheme_sexp_comments: false,
+            janet_long_strings: false,
+            hy_bracket_strings: false,
+            indents: format::indents_for("clojure"),
+        };
+        match language {
+            Language::Clojure => Self { ..common },
+            // Emacs Lisp has Common Lisp's forms, but `|` is an ordinary
+            // symbol character and there are no block comments.
+            Language::Elisp => Self {
+                indents: format::indents_for("elisp"),
+                ..common
+            },
+            Language::Guile => Self {
+                indents: format::indents_for("guile"),
+                lisp_vline_symbols: true,
+                lisp_block_comments: true,
+                guile_block_comments: true,
+                scheme_sexp_comments: true,
+                ..common
+            },
+            Language::Hy => Self {
+                indents: format::indents_for("hy"),
+                hy_bracket_strings: true,
+                ..common
+            },
+            Language::Janet => Self {
+                indents: format::indents_for("janet"),
+                comment_char: '#',
+                janet_long_strings: true,
+                ..common
+            },
+            Language::Lisp => Self {
+                indents: format::indents_for("lisp"),
+                lisp_vline_symbols: true,
+                lisp_block_comments: true,
+                ..common
+            },
+            Language::Picolisp => Self {
+                indents: format::indents_for("picolisp"),
+                comment_char: '#',
+                lisp_vline_symbols: true,
+                lisp_block_comments: true,
+                ..common
+            },
+            Language::Racket => Self {
+                indents: format::indents_for("racket"),
+                lisp_vline_symbols: true,
+                lisp_block_comments: true,
+                scheme_sexp_comments: true,
+                ..common
+            },
+            Language::Scheme => Self {
+                indents: format::indents_for("scheme"),
+                lisp_vline_symbols: true,
+                lisp_block_comments: true,
+                scheme_sexp_comments: true,
+                ..common
+            },
+        }
+    }
+
+    /// `options` with this language's syntax and indentation rules instead
+    /// of their own.
+    pub fn apply(self, options: &Options) -> Options {
+        Options {
+            comment_char: self.comment_char,
+            lisp_vline_symbols: self.lisp_vline_symbols,
+            lisp_block_comments: self.lisp_block_comments,
+            guile_block_comments: self.guile_block_comments,
+            scheme_sexp_comments: self.scheme_sexp_comments,
+            janet_long_strings: self.janet_long_strings,
+            hy_bracket_strings: self.hy_bracket_strings,
+            indents: self.indents,
+            ..options.clone()
+        }
+    }
+}
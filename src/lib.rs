@@ -7,8 +7,19 @@ extern crate serde_derive;
 extern crate unicode_segmentation;
 extern crate unicode_width;
 
+pub mod analyze;
+pub mod annotated;
 mod changes;
+mod documents;
+pub mod format;
+mod isolate;
+pub mod languages;
+pub mod outline;
+mod parallel;
+pub mod paredit;
 pub mod parinfer;
+pub mod paste;
+pub mod tab_stops;
 pub mod types;
 
 #[macro_use]
@@ -19,6 +30,12 @@ extern crate emacs;
 #[cfg(not(target_arch = "wasm32"))]
 extern crate libc;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod config;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod session;
+
 #[cfg(not(target_arch = "wasm32"))]
 mod c_wrapper;
 
@@ -39,11 +56,10 @@ pub use emacs_wrapper::init;
 // WebAssembly-specific stuff
 
 #[cfg(target_arch = "wasm32")]
-#[macro_use]
-extern crate stdweb;
+extern crate serde_wasm_bindgen;
 
 #[cfg(target_arch = "wasm32")]
-use stdweb::js_export;
+extern crate wasm_bindgen;
 
 mod common_wrapper;
 
@@ -51,10 +67,7 @@ mod common_wrapper;
 mod wasm_wrapper;
 
 #[cfg(target_arch = "wasm32")]
-#[js_export]
-pub fn run_parinfer(input: String) -> String {
-    wasm_wrapper::run_parinfer(input)
-}
+pub use wasm_wrapper::{indent_mode, paren_mode, run_parinfer, smart_mode};
 
 #[cfg(windows)]
 extern crate winapi;
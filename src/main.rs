@@ -9,19 +9,41 @@ extern crate serde_derive;
 extern crate unicode_segmentation;
 extern crate unicode_width;
 
+mod analyze;
+mod annotated;
 mod changes;
+mod check;
 mod cli_options;
+mod config;
+mod diff;
+mod documents;
+mod files;
+mod format;
+mod isolate;
 mod kakoune;
+mod languages;
+mod outline;
+mod parallel;
+mod paredit;
 mod parinfer;
+mod paste;
+mod sarif;
+mod session;
+mod snippet;
+mod tab_stops;
 mod types;
 
 use cli_options::OutputType;
 use kakoune::kakoune_output;
 use std::env;
+use std::fs;
 use std::io;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
 use types::*;
 
+const STDIN_FILENAME: &str = "<stdin>";
+
 fn parse_args() -> cli_options::Options {
     let args: Vec<String> = env::args().collect();
     cli_options::Options::parse(&args[1..]).expect("failed to parse options")
@@ -33,13 +55,204 @@ fn json_output(_request: &Request, answer: Answer) -> (String, i32) {
     (text, error_code)
 }
 
-fn text_output(_request: &Request, answer: Answer) -> (String, i32) {
-    if answer.success {
+fn text_output(filename: &str, request: &Request, answer: Answer, color: bool) -> (String, i32) {
+    let render = |e: &Error| snippet::render_error(filename, &request.text, e, color);
+    if request.mode == "analyze" {
+        let messages = answer.errors.iter().map(render).collect::<Vec<_>>();
+        (messages.join("\n"), if answer.success { 0 } else { 1 })
+    } else if answer.success {
         (answer.text.into_owned(), 0)
     } else {
         match answer.error {
             None => (String::from("parinfer-rust: unknown error.\n"), 1),
-            Some(e) => (format!("parinfer-rust: {}\n", e.message), 1),
+            Some(ref e) => (render(e), 1),
+        }
+    }
+}
+
+fn annotated_output(answer: Answer) -> (String, i32) {
+    let text = annotated::print(&annotated::Annotated::from_answer(&answer));
+    (text, if answer.success { 0 } else { 1 })
+}
+
+// Every error is listed, since `--isolate-forms` and `--document` report
+// each of the parts they couldn't process.
+fn diagnostics_output(filename: &str, answer: Answer) -> (String, i32) {
+    let errors = if answer.errors.is_empty() {
+        answer.error.iter().collect::<Vec<_>>()
+    } else {
+        answer.errors.iter().collect()
+    };
+    let error_code = if answer.success && errors.is_empty() {
+        0
+    } else {
+        1
+    };
+    let lines = errors.into_iter().map(|e| snippet::render_diagnostic(filename, e));
+    (lines.collect(), error_code)
+}
+
+fn diff_output(filename: &str, request: &Request, answer: Answer) -> (String, i32) {
+    if request.mode == "analyze" || !answer.success {
+        return text_output(filename, request, answer, false);
+    }
+    (diff::unified(filename, &request.text, &answer.text), 0)
+}
+
+fn output(opts: &cli_options::Options, filename: &str, request: &Request, answer: Answer) -> (String, i32) {
+    match opts.output_type() {
+        OutputType::Annotated => annotated_output(answer),
+        OutputType::Diagnostics => diagnostics_output(filename, answer),
+        OutputType::Diff => diff_output(filename, request, answer),
+        OutputType::Json => json_output(request, answer),
+        OutputType::Kakoune => kakoune_output(request, answer),
+        OutputType::Sarif => unreachable!("SARIF is reported by emit"),
+        OutputType::Text => text_output(filename, request, answer, io::stdout().is_terminal()),
+    }
+}
+
+// Diagnostics and SARIF reports list every error when they can.
+fn prepare(opts: &cli_options::Options, mut request: Request) -> Request {
+    if let OutputType::Diagnostics | OutputType::Sarif = opts.output_type() {
+        request.options.report_all_errors = true;
+    }
+    request
+}
+
+// With `--explain`, text output notes why parinfer changed what it did on
+// stderr, keeping stdout for the text itself.
+fn explain(filename: &str, answer: &Answer) -> io::Result<()> {
+    let notes: String = answer
+        .trace
+        .iter()
+        .map(|decision| snippet::render_decision(filename, decision))
+        .collect();
+    io::stderr().write_all(notes.as_bytes())
+}
+
+// SARIF results are collected into one report for all inputs and printed at
+// the end; everything else is printed as it is produced.
+fn emit(
+    opts: &cli_options::Options,
+    filename: &str,
+    request: &Request,
+    answer: Answer,
+    report: &mut sarif::Report,
+) -> io::Result<i32> {
+    if let OutputType::Sarif = opts.output_type() {
+        report.add(filename, request, &answer);
+        return Ok(if answer.success { 0 } else { 1 });
+    }
+    if let OutputType::Text = opts.output_type() {
+        explain(filename, &answer)?;
+    }
+    let (output, error_code) = output(opts, filename, request, answer);
+    io::stdout().write_all(output.as_bytes())?;
+    Ok(error_code)
+}
+
+fn check_output(
+    opts: &cli_options::Options,
+    filename: &str,
+    request: &Request,
+    report: &mut sarif::Report,
+) -> io::Result<i32> {
+    let finding = match check::check(request, &opts.check_modes()) {
+        None => return Ok(0),
+        Some(finding) => finding,
+    };
+    let output = match (opts.output_type(), &finding) {
+        (OutputType::Sarif, check::Finding::Failed { error, .. }) => {
+            report.add_error(filename, error);
+            return Ok(1);
+        }
+        (OutputType::Sarif, check::Finding::Changed { mode, text, .. }) => {
+            report.add_changes(filename, mode, &request.text, text);
+            return Ok(1);
+        }
+        (OutputType::Diff, check::Finding::Changed { text, .. }) => {
+            diff::unified(filename, &request.text, text)
+        }
+        _ => finding.describe(filename),
+    };
+    io::stdout().write_all(output.as_bytes())?;
+    Ok(1)
+}
+
+fn process_file(opts: &cli_options::Options, path: &Path, report: &mut sarif::Report) -> io::Result<i32> {
+    let filename = path.display().to_string();
+    let request = prepare(opts, opts.file_request(path)?);
+    if opts.check() {
+        return check_output(opts, &filename, &request, report);
+    }
+    let answer = parinfer::process(&request);
+    session::record(&request, &answer);
+    if !opts.in_place() || request.mode == "analyze" {
+        return emit(opts, &filename, &request, answer, report);
+    }
+    if !answer.success {
+        let (output, error_code) = text_output(&filename, &request, answer, io::stderr().is_terminal());
+        io::stderr().write_all(output.as_bytes())?;
+        return Ok(error_code);
+    }
+    explain(&filename, &answer)?;
+    if answer.text != request.text {
+        files::write_atomically(path, &answer.text)?;
+    }
+    Ok(0)
+}
+
+fn process_files(opts: &cli_options::Options, report: &mut sarif::Report) -> i32 {
+    let paths = match files::expand(opts.paths(), &opts.extensions()) {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("parinfer-rust: {}", e);
+            return 1;
+        }
+    };
+    let mut error_code = 0;
+    for path in paths {
+        match process_file(opts, &path, report) {
+            Ok(code) => error_code = error_code.max(code),
+            Err(e) => {
+                eprintln!("parinfer-rust: {}: {}", path.display(), e);
+                error_code = 1;
+            }
+        }
+    }
+    error_code
+}
+
+fn process_stdin(opts: &cli_options::Options, report: &mut sarif::Report) -> io::Result<i32> {
+    let request = prepare(opts, opts.request(&mut io::stdin())?);
+    if opts.check() {
+        return check_output(opts, STDIN_FILENAME, &request, report);
+    }
+    let answer = parinfer::process(&request);
+    session::record(&request, &answer);
+    emit(opts, STDIN_FILENAME, &request, answer, report)
+}
+
+fn replay(log: &str) -> i32 {
+    let replay = match fs::File::open(log).and_then(|f| session::replay(io::BufReader::new(f))) {
+        Ok(replay) => replay,
+        Err(e) => {
+            eprintln!("parinfer-rust: {}: {}", log, e);
+            return 2;
+        }
+    };
+    match replay.mismatch {
+        None => {
+            println!("{}: all {} answers match", log, replay.count);
+            0
+        }
+        Some(mismatch) => {
+            println!("{}:{}: answer differs", log, mismatch.line_no);
+            println!("request: {}", serde_json::to_string(&mismatch.request).unwrap());
+            for (field, expected, actual) in mismatch.differences() {
+                println!("{}:\n  logged:   {}\n  replayed: {}", field, expected, actual);
+            }
+            1
         }
     }
 }
@@ -48,19 +261,50 @@ pub fn main() {
     let opts = parse_args();
     if opts.want_help() {
         print!("{}", cli_options::usage());
+    } else if let Some(log) = opts.replay() {
+        std::process::exit(replay(&log));
+    } else if opts.in_place() && !opts.check() && opts.paths().is_empty() {
+        eprintln!("parinfer-rust: --in-place needs at least one FILE or DIR");
+        std::process::exit(1);
     } else {
-        let request = opts
-            .request(&mut io::stdin())
-            .expect("unable to parse options");
-        let answer = parinfer::process(&request);
-        let (output, error_code) = match opts.output_type() {
-            OutputType::Json => json_output(&request, answer),
-            OutputType::Kakoune => kakoune_output(&request, answer),
-            OutputType::Text => text_output(&request, answer),
+        let mut report = sarif::Report::new();
+        let error_code = if opts.paths().is_empty() {
+            process_stdin(&opts, &mut report).expect("unable to process stdin")
+        } else {
+            process_files(&opts, &mut report)
         };
-        io::stdout()
-            .write_all(output.as_bytes())
-            .expect("unable to write output");
+        if let OutputType::Sarif = opts.output_type() {
+            io::stdout()
+                .write_all(report.to_json().as_bytes())
+                .expect("unable to write output");
+        }
         std::process::exit(error_code);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &[&str], text: &str) -> (String, i32) {
+        let args: Vec<String> = args.iter().map(|&arg| String::from(arg)).collect();
+        let opts = cli_options::Options::parse(&args).unwrap();
+        let request = prepare(&opts, opts.request(&mut text.as_bytes()).unwrap());
+        let answer = parinfer::process(&request);
+        output(&opts, STDIN_FILENAME, &request, answer)
+    }
+
+    #[test]
+    fn diagnostics_list_the_forms_isolate_forms_left_alone() {
+        let text = "(def a \"unclosed)\n\n(defn b [x]\n  (+ x 1))";
+        let (output, error_code) = run(&["--isolate-forms", "--output-format=diagnostics"], text);
+        assert_eq!(
+            output,
+            "<stdin>:1:8: error: String is missing a closing quote. [unclosed-quote]\n"
+        );
+        assert_eq!(error_code, 1);
+
+        let (output, error_code) = run(&["--output-format=diagnostics"], "(foo)");
+        assert_eq!((output.as_str(), error_code), ("", 0));
+    }
+}
@@ -0,0 +1,229 @@
+//! The top-level forms of a text, for outline views, and the lists an
+//! editor can fold.
+
+use crate::paredit::{self, Form, List, Source};
+use crate::types::*;
+use std::borrow::Cow;
+
+fn first_word<'a>(source: &Source<'a>, form: &Form) -> Option<&'a str> {
+    let text = &source.text[form.start..form.end];
+    match form.list {
+        None if source.is_atom(text) => Some(text),
+        _ => None,
+    }
+}
+
+// Metadata like `^:private` or `^{:doc "…"}`, and forms discarded with `#_`,
+// which come before the name they belong with.
+fn is_ignored(text: &str) -> bool {
+    text.starts_with('^') || text.starts_with("#^") || text.starts_with("#_")
+}
+
+// What a form names after its head: the first form after any metadata, a
+// symbol without reader macros like `'`, or a whole `(` list, as in Scheme's
+// `(define (foo x) …)`.  Vectors, strings, keywords and numbers name
+// nothing.
+fn form_name(source: &Source, forms: &[Form]) -> Option<String> {
+    let form = forms
+        .iter()
+        .find(|form| !is_ignored(&source.text[form.start..form.end]))?;
+    match form.list {
+        Some(list) if list.open_ch == "(" => {
+            let words: Vec<&str> = source.text[form.start..form.end]
+                .split_whitespace()
+                .collect();
+            Some(words.join(" "))
+        }
+        Some(_) => None,
+        None => {
+            let symbol = first_word(source, form)?.trim_start_matches(['\'', '`', '~', '@', '#']);
+            let is_symbol = symbol
+                .chars()
+                .next()
+                .map(|ch| !ch.is_ascii_digit() && !":\\".contains(ch))
+                .unwrap_or(false);
+            is_symbol.then(|| String::from(symbol))
+        }
+    }
+}
+
+fn outline_form(source: &Source, form: &Form) -> OutlineForm {
+    let (line_no, x) = paredit::position(source.text, form.start);
+    let (end_line_no, end_x) = paredit::position(source.text, form.end);
+    let children = source.children(form.list);
+    let head = children.first().and_then(|child| first_word(source, child));
+    let name = head.zip(form_name(source, children.get(1..).unwrap_or(&[])));
+    let name = name.map(|(head, name)| format!("{} {}", head, name));
+    OutlineForm {
+        line_no,
+        x,
+        end_line_no,
+        end_x,
+        head: head.map(String::from),
+        name,
+    }
+}
+
+fn add_folds(source: &Source, lists: &[List], folds: &mut Vec<Fold>) {
+    for list in lists {
+        let end = list.close + list.close_ch.len();
+        let (line_no, x) = paredit::position(source.text, list.open);
+        let (end_line_no, end_x) = paredit::position(source.text, end);
+        if line_no < end_line_no {
+            folds.push(Fold {
+                line_no,
+                x,
+                end_line_no,
+                end_x,
+            });
+        }
+        add_folds(source, &list.children, folds);
+    }
+}
+
+/// Lists the top-level forms of `text` and every list in it spanning more
+/// than one line, in the order they start.
+pub fn outline(text: &str, options: &Options) -> Result<Outline, Error> {
+    let source = Source::parse(text, options)?;
+    let forms = source
+        .children(None)
+        .iter()
+        .filter(|form| form.list.is_some())
+        .map(|form| outline_form(&source, form))
+        .collect();
+    let mut folds = vec![];
+    add_folds(&source, &source.top_level, &mut folds);
+    Ok(Outline { forms, folds })
+}
+
+/// Leaves the text alone and reports its top-level forms and folds in
+/// `outline`.
+pub fn outline_mode<'a>(text: &'a str, options: &Options) -> Answer<'a> {
+    let (outline, error) = match outline(text, options) {
+        Ok(outline) => (Some(outline), None),
+        Err(error) => (None, Some(error)),
+    };
+    Answer {
+        text: Cow::from(text),
+        success: error.is_none(),
+        error,
+        cursor_x: options.cursor_x,
+        cursor_line: options.cursor_line,
+        tab_stops: vec![],
+        paren_trails: vec![],
+        parens: vec![],
+        errors: vec![],
+        outline,
+        positions: options.positions.clone(),
+        trace: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outline(text: &str) -> Outline {
+        let options: Options = serde_json::from_str("{}").unwrap();
+        super::outline(text, &options).unwrap()
+    }
+
+    fn names(text: &str) -> Vec<Option<String>> {
+        outline(text)
+            .forms
+            .into_iter()
+            .map(|form| form.name)
+            .collect()
+    }
+
+    #[test]
+    fn forms_have_positions_heads_and_names() {
+        let outline = outline("(ns foo.bar)\n\n(defn foo\n  [x]\n  x)\n");
+        assert_eq!(
+            outline.forms,
+            vec![
+                OutlineForm {
+                    line_no: 0,
+                    x: 0,
+                    end_line_no: 0,
+                    end_x: 12,
+                    head: Some(String::from("ns")),
+                    name: Some(String::from("ns foo.bar")),
+                },
+                OutlineForm {
+                    line_no: 2,
+                    x: 0,
+                    end_line_no: 4,
+                    end_x: 4,
+                    head: Some(String::from("defn")),
+                    name: Some(String::from("defn foo")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn names_take_the_form_after_the_head() {
+        assert_eq!(
+            names("(define (bar\n         x)\n  x)"),
+            vec![Some(String::from("define (bar x)"))]
+        );
+        assert_eq!(
+            names("(defn ^:private ^{:doc \"a\"} foo [])\n(def #_x 'bar 1)"),
+            vec![
+                Some(String::from("defn foo")),
+                Some(String::from("def bar"))
+            ]
+        );
+        assert_eq!(
+            names("(foo)\n((bar) baz)\n(\"a\" b)"),
+            vec![None, None, None]
+        );
+        assert_eq!(
+            names("(let [x 1]\n  x)\n(foo :bar)\n(foo 1)\n(foo \"a\")"),
+            vec![None, None, None, None]
+        );
+    }
+
+    #[test]
+    fn prefixes_belong_to_the_form() {
+        let outline = outline("#?(:clj 1)\n'(a b)");
+        let starts: Vec<(LineNumber, Column)> = outline
+            .forms
+            .iter()
+            .map(|form| (form.line_no, form.x))
+            .collect();
+        assert_eq!(starts, vec![(0, 0), (1, 0)]);
+        assert_eq!(outline.forms[0].head, Some(String::from(":clj")));
+    }
+
+    #[test]
+    fn folds_are_nested_multiline_lists() {
+        assert_eq!(
+            outline("(defn foo []\n  (let [x 1]\n    x)\n  (bar))").folds,
+            vec![
+                Fold {
+                    line_no: 0,
+                    x: 0,
+                    end_line_no: 3,
+                    end_x: 8,
+                },
+                Fold {
+                    line_no: 1,
+                    x: 2,
+                    end_line_no: 2,
+                    end_x: 6,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unbalanced_text_is_an_error() {
+        let options: Options = serde_json::from_str("{}").unwrap();
+        assert_eq!(
+            super::outline("(foo", &options).unwrap_err().name,
+            ErrorName::UnclosedParen
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Processing large texts in indent mode on several threads, a run of
+//! top-level forms on each.
+
+use crate::analyze::{is_top_level_line, TopLevelChunk};
+use crate::isolate::{add_chunk_answer, chunk_options, chunked_answer};
+use crate::parinfer::{get_line_ending, indent_mode, split_lines};
+use crate::types::*;
+use std::borrow::Cow;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+// Texts shorter than this aren't worth the threads.
+const PARALLEL_THRESHOLD: usize = 64 * 1024;
+
+// How many threads to process `text` with in indent mode, if any.
+pub(crate) fn parallel_workers(text: &str, options: &Options) -> Option<usize> {
+    // Collapsing blank lines, the final newline and the explanation of the
+    // paren trail closed at the end of the text all need the whole text.
+    if text.len() < PARALLEL_THRESHOLD
+        || options.max_blank_lines.is_some()
+        || options.final_newline
+        || options.explain
+    {
+        return None;
+    }
+    thread::available_parallelism()
+        .ok()
+        .map(NonZeroUsize::get)
+        .filter(|&workers| workers > 1)
+}
+
+// Splits the lines into `count` runs of top-level forms of about the same
+// size.  Each run after the first starts on a line whose first character is
+// code at column zero, where indent mode closes every paren still open, so
+// the runs can be processed on their own.  That is, unless the line is inside
+// a string or block comment, but then the run before it fails.
+fn parallel_chunks(lines: &[&str], count: usize, options: &Options) -> Vec<TopLevelChunk> {
+    let size = lines.iter().map(|line| line.len() + 1).sum::<usize>() / count;
+    let mut chunks = vec![];
+    let mut start = 0;
+    let mut length = 0;
+    for (line_no, line) in lines.iter().enumerate() {
+        if length >= size && is_top_level_line(line) && !line.starts_with(options.comment_char) {
+            chunks.push(TopLevelChunk {
+                start,
+                end: line_no,
+                error: None,
+            });
+            start = line_no;
+            length = 0;
+        }
+        length += line.len() + 1;
+    }
+    chunks.push(TopLevelChunk {
+        start,
+        end: lines.len(),
+        error: None,
+    });
+    chunks
+}
+
+/// Runs indent mode over runs of top-level forms on `workers` threads, and
+/// stitches their answers together.  This is the same as running it over the
+/// whole text when every run succeeds; otherwise, or if a worker panics, it
+/// gives up with `None`.
+pub(crate) fn parallel_indent_mode<'a>(
+    text: &'a str,
+    options: &Options,
+    workers: usize,
+) -> Option<Answer<'a>> {
+    let lines = split_lines(text);
+    let line_ending = get_line_ending(text);
+    let chunks = parallel_chunks(&lines, workers * 4, options);
+    let chunk_texts: Vec<String> = chunks
+        .iter()
+        .map(|chunk| lines[chunk.start..chunk.end].join(line_ending))
+        .collect();
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let mut chunk_answers: Vec<(usize, Answer<'_>)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut answers = vec![];
+                    while !failed.load(Ordering::Relaxed) {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= chunks.len() {
+                            break;
+                        }
+                        let answer =
+                            indent_mode(&chunk_texts[i], &chunk_options(options, &chunks[i]));
+                        if !answer.success {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        answers.push((i, answer));
+                    }
+                    answers
+                })
+            })
+            .collect();
+        // A worker which panicked is a failure like any other, so the text
+        // is processed again on this thread, where the panic can be caught
+        // and described properly if it happens again.
+        let mut answers = vec![];
+        for handle in handles {
+            match handle.join() {
+                Ok(worker_answers) => answers.extend(worker_answers),
+                Err(_) => failed.store(true, Ordering::Relaxed),
+            }
+        }
+        answers
+    });
+    if failed.into_inner() {
+        return None;
+    }
+
+    chunk_answers.sort_by_key(|(i, _)| *i);
+    let mut output: Vec<String> = vec![];
+    let mut answer = chunked_answer(options);
+    for (i, chunk_answer) in chunk_answers {
+        add_chunk_answer(&mut answer, &mut output, options, &chunks[i], chunk_answer);
+    }
+    answer.text = Cow::from(output.join(line_ending));
+    Some(answer)
+}
+
+#[cfg(test)]
+#[test]
+fn parallel_indent_mode_matches_indent_mode() {
+    let options: Options = serde_json::from_str("{}").unwrap();
+    let same = |text: &str, options: &Options| {
+        let expected = serde_json::to_value(indent_mode(text, options)).unwrap();
+        parallel_indent_mode(text, options, 4)
+            .map(|answer| serde_json::to_value(answer).unwrap() == expected)
+    };
+
+    let long_file = include_str!("../benches/perf/really_long_file");
+    assert!(parallel_chunks(&split_lines(long_file), 16, &options).len() > 1);
+    assert_eq!(same(long_file, &options), Some(true));
+    assert_eq!(
+        same(
+            include_str!("../benches/perf/really_long_file_with_unclosed_paren"),
+            &options
+        ),
+        Some(true)
+    );
+    // the unclosed string makes a run fail, so the whole text is needed
+    assert_eq!(
+        same(
+            include_str!("../benches/perf/really_long_file_with_unclosed_quote"),
+            &options
+        ),
+        None
+    );
+
+    let lines = split_lines(long_file);
+    let cursor_line = lines.len() / 2;
+    let with_cursor = Options {
+        cursor_line: Some(cursor_line),
+        cursor_x: Some(lines[cursor_line].len()),
+        positions: vec![Position {
+            line_no: lines.len() - 1,
+            x: 0,
+        }],
+        trim_trailing_whitespace: true,
+        ..options.clone()
+    };
+    assert_eq!(same(long_file, &with_cursor), Some(true));
+}
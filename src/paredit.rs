@@ -0,0 +1,725 @@
+//! Paredit-style structural edits.
+//!
+//! Each operation works on the lists around the cursor, taken from paren
+//! mode's paren tree, and the edited text goes through paren mode again so
+//! that indentation follows the new structure.
+
+use crate::parinfer;
+use crate::types::*;
+use std::borrow::Cow;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    SlurpForward,
+    SlurpBackward,
+    BarfForward,
+    BarfBackward,
+    Splice,
+    Raise,
+    Wrap(&'static str),
+    Split,
+    Join,
+}
+
+const OPERATIONS: [(&str, Operation); 11] = [
+    ("slurp-forward", Operation::SlurpForward),
+    ("slurp-backward", Operation::SlurpBackward),
+    ("barf-forward", Operation::BarfForward),
+    ("barf-backward", Operation::BarfBackward),
+    ("splice", Operation::Splice),
+    ("raise", Operation::Raise),
+    ("wrap", Operation::Wrap("(")),
+    ("wrap-square", Operation::Wrap("[")),
+    ("wrap-curly", Operation::Wrap("{")),
+    ("split", Operation::Split),
+    ("join", Operation::Join),
+];
+
+impl Operation {
+    /// The mode name that requests this operation.
+    pub fn name(self) -> &'static str {
+        OPERATIONS
+            .iter()
+            .find(|(_, operation)| *operation == self)
+            .map(|(name, _)| *name)
+            .unwrap()
+    }
+}
+
+impl std::str::FromStr for Operation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Operation, String> {
+        OPERATIONS
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, operation)| *operation)
+            .ok_or_else(|| format!("unknown operation: {}", s))
+    }
+}
+
+// Positions below are byte offsets into the text.
+
+#[derive(Clone, Debug)]
+pub(crate) struct List<'a> {
+    pub open: usize,
+    pub close: usize,
+    pub open_ch: &'a str,
+    pub close_ch: &'static str,
+    pub children: Vec<List<'a>>,
+}
+
+/// A form between the parens of a list, or at the top level.  `start`
+/// includes any reader prefix, like the `'` of `'(a b)`.
+#[derive(Clone, Debug)]
+pub(crate) struct Form<'a, 'b> {
+    pub start: usize,
+    pub end: usize,
+    pub list: Option<&'b List<'a>>,
+}
+
+struct Edit {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+fn insert(at: usize, text: &str) -> Edit {
+    Edit {
+        start: at,
+        end: at,
+        text: String::from(text),
+    }
+}
+
+fn delete(start: usize, end: usize) -> Edit {
+    Edit {
+        start,
+        end,
+        text: String::new(),
+    }
+}
+
+/// The text with the lists paren mode found in it.
+pub(crate) struct Source<'a> {
+    pub text: &'a str,
+    line_starts: Vec<usize>,
+    comment_char: char,
+    sexp_comments: bool,
+    string_delimiters: &'a [String],
+    pub top_level: Vec<List<'a>>,
+    /// Whether each line starts inside a string or a block comment.
+    pub stringish_lines: Vec<bool>,
+}
+
+impl<'a> Source<'a> {
+    /// Parses `text`, failing with paren mode's error if it isn't balanced.
+    pub fn parse(text: &'a str, options: &'a Options) -> Result<Source<'a>, Error> {
+        let (top_level, stringish_lines) = parinfer::paren_tree(text, options)?;
+        let mut source = Source {
+            text,
+            line_starts: std::iter::once(0)
+                .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+                .collect(),
+            comment_char: options.comment_char,
+            sexp_comments: options.scheme_sexp_comments,
+            string_delimiters: &options.string_delimiters,
+            top_level: vec![],
+            stringish_lines,
+        };
+        source.top_level = top_level.iter().map(|paren| source.list(paren)).collect();
+        Ok(source)
+    }
+
+    fn line(&self, line_no: LineNumber) -> &'a str {
+        let end = self
+            .line_starts
+            .get(line_no + 1)
+            .map(|&start| start - 1)
+            .unwrap_or(self.text.len());
+        let line = &self.text[self.line_starts[line_no]..end];
+        line.strip_suffix('\r').unwrap_or(line)
+    }
+
+    fn offset(&self, line_no: LineNumber, x: Column) -> usize {
+        self.line_starts[line_no] + parinfer::column_byte_index(self.line(line_no), x)
+    }
+
+    fn list(&self, paren: &Paren<'a>) -> List<'a> {
+        let closer = paren.closer.as_ref().unwrap();
+        List {
+            open: self.offset(paren.input_line_no, paren.input_x),
+            close: self.offset(closer.input_line_no, closer.input_x),
+            open_ch: paren.ch,
+            close_ch: closer.ch,
+            children: paren
+                .children
+                .iter()
+                .map(|child| self.list(child))
+                .collect(),
+        }
+    }
+
+    // The lists containing `pos`, outermost first.  The cursor is inside a
+    // list from just after its opener to just before its closer.
+    pub fn enclosing(&self, pos: usize) -> Vec<&List<'a>> {
+        let mut path = vec![];
+        let mut lists = &self.top_level;
+        while let Some(list) = lists.iter().find(|l| l.open < pos && pos <= l.close) {
+            path.push(list);
+            lists = &list.children;
+        }
+        path
+    }
+
+    fn is_string_delimiter(&self, rest: &str) -> Option<usize> {
+        self.string_delimiters
+            .iter()
+            .find(|d| !d.is_empty() && rest.starts_with(d.as_str()))
+            .map(|d| d.len())
+    }
+
+    /// Whether the text of a form is an atom, rather than a string.  Lists
+    /// are told apart by `Form::list`.
+    pub fn is_atom(&self, form: &str) -> bool {
+        self.is_string_delimiter(form).is_none()
+    }
+
+    // Splits `start..end` into forms, treating the lists in it as opaque.
+    // Anything parinfer doesn't see as a list is scanned here: whitespace,
+    // comments, strings and atoms.
+    fn forms<'b>(&self, start: usize, end: usize, lists: &'b [List<'a>]) -> Vec<Form<'a, 'b>> {
+        let mut forms = vec![];
+        let mut lists = lists.iter().peekable();
+        let mut prefix: Option<usize> = None;
+        let mut i = start;
+        while i < end {
+            if let Some(list) = lists.next_if(|list| list.open == i) {
+                let close = list.close + list.close_ch.len();
+                forms.push(Form {
+                    start: prefix.take().unwrap_or(i),
+                    end: close,
+                    list: Some(list),
+                });
+                i = close;
+                continue;
+            }
+
+            let rest = &self.text[i..end];
+            let ch = rest.chars().next().unwrap();
+            if ch.is_whitespace() {
+                i += ch.len_utf8();
+            } else if ch == self.comment_char {
+                i += rest.find('\n').unwrap_or(rest.len());
+            } else if let Some(len) = self.is_string_delimiter(rest) {
+                let delimiter = &rest[..len];
+                let mut j = len;
+                while j < rest.len() && !rest[j..].starts_with(delimiter) {
+                    let skip = if rest[j..].starts_with('\\') { 2 } else { 1 };
+                    for ch in rest[j..].chars().take(skip) {
+                        j += ch.len_utf8();
+                    }
+                }
+                let string_end = i + (j + len).min(rest.len());
+                forms.push(Form {
+                    start: prefix.take().unwrap_or(i),
+                    end: string_end,
+                    list: None,
+                });
+                i = string_end;
+            } else {
+                let mut j = i;
+                let mut chars = self.text[i..end].char_indices();
+                while let Some((k, ch)) = chars.next() {
+                    let at = i + k;
+                    let at_list = lists.peek().map(|list| list.open == at).unwrap_or(false);
+                    // The `;` of a `#;` datum comment doesn't start a comment.
+                    let is_sexp_comment =
+                        self.sexp_comments && at == i + 1 && rest.starts_with("#;");
+                    if at > i
+                        && (at_list
+                            || ch.is_whitespace()
+                            || (ch == self.comment_char && !is_sexp_comment)
+                            || "()[]{}".contains(ch)
+                            || self.is_string_delimiter(&self.text[at..end]).is_some())
+                    {
+                        break;
+                    }
+                    j = at + ch.len_utf8();
+                    if ch == '\\' {
+                        if let Some((k, ch)) = chars.next() {
+                            j = i + k + ch.len_utf8();
+                        }
+                    }
+                }
+                let adjacent_list = lists.peek().map(|list| list.open == j).unwrap_or(false);
+                if adjacent_list {
+                    prefix.get_or_insert(i);
+                } else {
+                    forms.push(Form {
+                        start: prefix.take().unwrap_or(i),
+                        end: j,
+                        list: None,
+                    });
+                }
+                i = j;
+            }
+        }
+        forms
+    }
+
+    /// Whether `pos`, directly inside `list` or at the top level, is inside
+    /// a string or a comment rather than in code.
+    pub fn is_in_string_or_comment(&self, list: Option<&List<'a>>, pos: usize) -> bool {
+        let forms = self.children(list);
+        let in_form = |at: usize| forms.iter().any(|form| form.start <= at && at < form.end);
+        let in_string = forms.iter().any(|form| {
+            let text = &self.text[form.start..form.end];
+            form.list.is_none() && !self.is_atom(text) && form.start < pos && pos < form.end
+        });
+        let start = list.map(|list| list.open + list.open_ch.len()).unwrap_or(0);
+        let line_start = self.text[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let in_comment = self.text[..pos]
+            .char_indices()
+            .skip_while(|&(i, _)| i < start.max(line_start))
+            .any(|(i, ch)| ch == self.comment_char && !in_form(i));
+        in_string || in_comment
+    }
+
+    /// The forms inside `list`, or at the top level.
+    pub fn children<'b>(&'b self, list: Option<&'b List<'a>>) -> Vec<Form<'a, 'b>> {
+        match list {
+            Some(list) => self.forms(list.open + 1, list.close, &list.children),
+            None => self.forms(0, self.text.len(), &self.top_level),
+        }
+    }
+}
+
+fn is_whitespace_at(text: &str, pos: usize) -> bool {
+    text[pos..]
+        .chars()
+        .next()
+        .map(char::is_whitespace)
+        .unwrap_or(true)
+}
+
+fn is_whitespace_before(text: &str, pos: usize) -> bool {
+    text[..pos]
+        .chars()
+        .next_back()
+        .map(char::is_whitespace)
+        .unwrap_or(true)
+}
+
+// The edits for an operation, and where the cursor ends up in the edited
+// text if it doesn't just move along with the text around it.
+type Plan = (Vec<Edit>, Option<usize>);
+
+fn plan(source: &Source, operation: Operation, pos: usize) -> Option<Plan> {
+    let text = source.text;
+    let path = source.enclosing(pos);
+    let innermost = path.last().copied();
+    // The forms around the list at `path[i]`, and its index among them.
+    let siblings = |i: usize| {
+        let forms = source.children(if i == 0 { None } else { Some(path[i - 1]) });
+        let index = forms
+            .iter()
+            .position(|form| form.list.map(|list| list.open) == Some(path[i].open))
+            .unwrap();
+        (forms, index)
+    };
+
+    match operation {
+        Operation::SlurpForward => (0..path.len()).rev().find_map(|i| {
+            let (forms, index) = siblings(i);
+            let next = forms.get(index + 1)?;
+            let list = path[i];
+            Some((
+                vec![
+                    delete(list.close, list.close + list.close_ch.len()),
+                    insert(next.end, list.close_ch),
+                ],
+                None,
+            ))
+        }),
+        Operation::SlurpBackward => (0..path.len()).rev().find_map(|i| {
+            let (forms, index) = siblings(i);
+            let prev = forms.get(index.checked_sub(1)?)?;
+            let opening = forms[index].start..path[i].open + path[i].open_ch.len();
+            Some((
+                vec![
+                    insert(prev.start, &text[opening.clone()]),
+                    delete(opening.start, opening.end),
+                ],
+                None,
+            ))
+        }),
+        Operation::BarfForward => {
+            let list = innermost?;
+            let children = source.children(Some(list));
+            if children.is_empty() {
+                return None;
+            }
+            let at = match children.len() {
+                1 => list.open + list.open_ch.len(),
+                n => children[n - 2].end,
+            };
+            let mut closer = String::from(list.close_ch);
+            if !is_whitespace_at(text, at) {
+                closer.push(' ');
+            }
+            Some((
+                vec![
+                    insert(at, &closer),
+                    delete(list.close, list.close + list.close_ch.len()),
+                ],
+                None,
+            ))
+        }
+        Operation::BarfBackward => {
+            let list = innermost?;
+            let children = source.children(Some(list));
+            if children.is_empty() {
+                return None;
+            }
+            let (forms, index) = siblings(path.len() - 1);
+            let opening = forms[index].start..list.open + list.open_ch.len();
+            let at = children.get(1).map(|form| form.start).unwrap_or(list.close);
+            let mut opener = String::new();
+            if !is_whitespace_before(text, at) {
+                opener.push(' ');
+            }
+            opener.push_str(&text[opening.clone()]);
+            // a cursor on the first form left in the list stays on it
+            let cursor = (pos == at).then(|| at - opening.len() + opener.len());
+            Some((
+                vec![delete(opening.start, opening.end), insert(at, &opener)],
+                cursor,
+            ))
+        }
+        Operation::Splice => {
+            let list = innermost?;
+            let (forms, index) = siblings(path.len() - 1);
+            Some((
+                vec![
+                    delete(forms[index].start, list.open + list.open_ch.len()),
+                    delete(list.close, list.close + list.close_ch.len()),
+                ],
+                None,
+            ))
+        }
+        Operation::Raise => {
+            innermost?;
+            let children = source.children(innermost);
+            let form = children.iter().find(|form| pos <= form.end)?;
+            let (forms, index) = siblings(path.len() - 1);
+            let outer = &forms[index];
+            let cursor = outer.start + (pos.max(form.start) - form.start);
+            Some((
+                vec![Edit {
+                    start: outer.start,
+                    end: outer.end,
+                    text: String::from(&text[form.start..form.end]),
+                }],
+                Some(cursor),
+            ))
+        }
+        Operation::Wrap(open_ch) => {
+            let close_ch = parinfer::match_paren(open_ch).unwrap();
+            let children = source.children(innermost);
+            match children.iter().find(|form| pos <= form.end) {
+                Some(form) => Some((
+                    vec![insert(form.start, open_ch), insert(form.end, close_ch)],
+                    Some(form.start + open_ch.len()),
+                )),
+                None => Some((
+                    vec![insert(pos, &format!("{}{}", open_ch, close_ch))],
+                    Some(pos + open_ch.len()),
+                )),
+            }
+        }
+        Operation::Split => {
+            let list = innermost?;
+            if source.is_in_string_or_comment(innermost, pos) {
+                return None;
+            }
+            // Split between the forms around the cursor, or inside the atom
+            // it is in, keeping any comments between them.
+            let children = source.children(innermost);
+            let left = children
+                .iter()
+                .filter(|form| form.start < pos)
+                .map(|form| form.end.min(pos))
+                .max()
+                .unwrap_or(list.open + list.open_ch.len());
+            let right = children
+                .iter()
+                .filter(|form| form.end > pos)
+                .map(|form| form.start.max(pos))
+                .min()
+                .unwrap_or(list.close);
+            if text[left..right].trim().is_empty() {
+                let replacement = format!("{} {}", list.close_ch, list.open_ch);
+                let cursor = left + replacement.len();
+                return Some((
+                    vec![Edit {
+                        start: left,
+                        end: right,
+                        text: replacement,
+                    }],
+                    Some(cursor),
+                ));
+            }
+            let cursor = right + list.close_ch.len() + list.open_ch.len();
+            Some((
+                vec![insert(left, list.close_ch), insert(right, list.open_ch)],
+                Some(cursor),
+            ))
+        }
+        Operation::Join => {
+            let children = source.children(innermost);
+            let next = children.iter().position(|form| pos <= form.start)?;
+            let (prev, next) = (&children[next.checked_sub(1)?], &children[next]);
+            let (prev_list, next_list) = (prev.list?, next.list?);
+            if prev_list.open_ch != next_list.open_ch || pos < prev.end {
+                return None;
+            }
+            Some((
+                vec![
+                    delete(prev_list.close, prev_list.close + prev_list.close_ch.len()),
+                    delete(next.start, next_list.open + next_list.open_ch.len()),
+                ],
+                None,
+            ))
+        }
+    }
+}
+
+// Applies edits, sorted and non-overlapping, to `text`.
+fn apply(text: &str, edits: &[Edit]) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut last = 0;
+    for edit in edits {
+        output.push_str(&text[last..edit.start]);
+        output.push_str(&edit.text);
+        last = edit.end;
+    }
+    output.push_str(&text[last..]);
+    output
+}
+
+// Where `offset` ends up once `edits` are applied.  It moves along with the
+// text around it, and one inside replaced text ends up where the
+// replacement starts.
+fn map_offset(edits: &[Edit], offset: usize) -> usize {
+    let mut shift = 0;
+    for edit in edits {
+        if offset <= edit.start {
+            break;
+        }
+        if offset < edit.end {
+            return (edit.start as isize + shift) as usize;
+        }
+        shift += edit.text.len() as isize - (edit.end - edit.start) as isize;
+    }
+    (offset as isize + shift) as usize
+}
+
+/// The line and column of a byte offset into `text`.
+pub(crate) fn position(text: &str, offset: usize) -> (LineNumber, Column) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let x = before[line_start..]
+        .graphemes(true)
+        .map(UnicodeWidthStr::width)
+        .sum();
+    (before.matches('\n').count(), x)
+}
+
+/// The edited text, before paren mode, and the options to run paren mode
+/// over it with, where the cursor and the tracked positions have moved along.
+/// Returns `None` if there is no cursor, the text isn't balanced, or the
+/// operation has nothing to work on.
+pub fn edit(operation: Operation, text: &str, options: &Options) -> Option<(String, Options)> {
+    let (cursor_line, cursor_x) = (options.cursor_line?, options.cursor_x?);
+    let source = Source::parse(text, options).ok()?;
+    if cursor_line >= source.line_starts.len() {
+        return None;
+    }
+
+    let pos = source.offset(cursor_line, cursor_x);
+    let (mut edits, cursor) = plan(&source, operation, pos)?;
+    edits.sort_by_key(|edit| (edit.start, edit.end));
+    let edited = apply(text, &edits);
+    let (cursor_line, cursor_x) =
+        position(&edited, cursor.unwrap_or_else(|| map_offset(&edits, pos)));
+    let positions = options
+        .positions
+        .iter()
+        .map(|p| {
+            if p.line_no >= source.line_starts.len() {
+                return *p;
+            }
+            let offset = map_offset(&edits, source.offset(p.line_no, p.x));
+            let (line_no, x) = position(&edited, offset);
+            Position { x, line_no }
+        })
+        .collect();
+    let options = Options {
+        cursor_x: Some(cursor_x),
+        cursor_line: Some(cursor_line),
+        prev_cursor_x: None,
+        prev_cursor_line: None,
+        prev_text: None,
+        selection_start_line: None,
+        changes: vec![],
+        positions,
+        ..options.clone()
+    };
+    Some((edited, options))
+}
+
+/// Runs a structural edit, then paren mode over the edited text.  When the
+/// edit doesn't apply, this is just paren mode.
+pub(crate) fn edit_mode<'a>(operation: Operation, text: &'a str, options: &Options) -> Answer<'a> {
+    match edit(operation, text, options) {
+        Some((edited, edited_options)) => unless_failed(
+            parinfer::paren_mode(&edited, &edited_options),
+            text,
+            options,
+        ),
+        None => parinfer::paren_mode(text, options),
+    }
+}
+
+// An answer for text edited inside the library, or if parinfer failed on
+// it, the error with the original text and cursor, so an editor applying
+// the text anyway doesn't get the half-made edit.
+fn unless_failed<'a>(answer: Answer<'_>, text: &'a str, options: &Options) -> Answer<'a> {
+    if answer.success {
+        return parinfer::owned_answer(answer);
+    }
+    Answer {
+        text: Cow::from(text),
+        cursor_x: options.cursor_x,
+        cursor_line: options.cursor_line,
+        tab_stops: vec![],
+        paren_trails: vec![],
+        positions: options.positions.clone(),
+        trace: vec![],
+        ..parinfer::owned_answer(answer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotated;
+
+    fn run(mode: &str, input: &str) -> String {
+        let input = annotated::parse(input).unwrap();
+        let mut options: Options = serde_json::from_str("{}").unwrap();
+        options.cursor_x = input.cursor_x;
+        options.cursor_line = input.cursor_line;
+        let request = Request {
+            mode: String::from(mode),
+            text: input.text,
+            options,
+        };
+        let answer = parinfer::process(&request);
+        annotated::print(&annotated::Annotated::from_answer(&answer))
+    }
+
+    #[test]
+    fn operation_names_round_trip() {
+        for (name, operation) in OPERATIONS.iter() {
+            assert_eq!(name.parse::<Operation>(), Ok(*operation));
+            assert_eq!(operation.name(), *name);
+        }
+        assert!("slurp".parse::<Operation>().is_err());
+    }
+
+    #[test]
+    fn slurp() {
+        assert_eq!(run("slurp-forward", "(a |b) c d"), "(a |b c) d");
+        assert_eq!(run("slurp-forward", "(a (b|) \"c d\")"), "(a (b| \"c d\"))");
+        assert_eq!(run("slurp-forward", "(a (b|)) c"), "(a (b|) c)");
+        assert_eq!(run("slurp-forward", "(a b|)\n'(c d)"), "(a b|\n '(c d))");
+        assert_eq!(
+            run("slurp-forward", "(a |b) ; c)\n\\) d"),
+            "(a |b ; c)\n \\)) d"
+        );
+        assert_eq!(run("slurp-backward", "a b (c|)"), "a (b c|)");
+        assert_eq!(run("slurp-backward", "a #{b|}"), "#{a b|}");
+    }
+
+    #[test]
+    fn barf() {
+        assert_eq!(run("barf-forward", "(a |b c)"), "(a |b) c");
+        assert_eq!(run("barf-forward", "(|a)"), "(|) a");
+        assert_eq!(run("barf-backward", "(a b |c)"), "a (b |c)");
+        assert_eq!(run("barf-backward", "'(|a)"), "|a '()");
+    }
+
+    #[test]
+    fn splice_and_raise() {
+        assert_eq!(run("splice", "(a '(b |c) d)"), "(a b |c d)");
+        assert_eq!(run("raise", "(a (b |c d))"), "(a |c)");
+        assert_eq!(run("raise", "(a (b |[c] d))"), "(a |[c])");
+    }
+
+    #[test]
+    fn wrap() {
+        assert_eq!(run("wrap", "(a |b c)"), "(a (|b) c)");
+        assert_eq!(run("wrap-square", "(a b|)"), "(a [|b])");
+        assert_eq!(run("wrap-curly", "(a |)"), "(a {|})");
+    }
+
+    #[test]
+    fn split_and_join() {
+        assert_eq!(run("split", "(a b |c)"), "(a b) (|c)");
+        assert_eq!(run("split", "[a |b]"), "[a] [|b]");
+        assert_eq!(run("split", "(a \"b| c\" d)"), "(a \"b| c\" d)");
+        assert_eq!(run("split", "(a b ; c| d\n e)"), "(a b ; c| d\n e)");
+        assert_eq!(run("split", "(a b ; c\n |d)"), "(a b) ; c\n(|d)");
+        assert_eq!(run("join", "(a b) |(c d)"), "(a b |c d)");
+        assert_eq!(run("join", "(a b) |[c d]"), "(a b) |[c d]");
+    }
+
+    #[test]
+    fn results_are_reindented_by_paren_mode() {
+        assert_eq!(
+            run("slurp-forward", "(let [x 1]\n  (foo|))\nbar"),
+            "(let [x 1]\n  (foo|)\n bar)"
+        );
+        assert_eq!(
+            run("slurp-forward", "(defn f [] (foo|))\n(bar\n baz)"),
+            "(defn f [] (foo|)\n (bar\n  baz))"
+        );
+        assert_eq!(run("barf-backward", "(a\n  |b\n  c)"), "a\n  (|b\n   c)");
+    }
+
+    #[test]
+    fn operations_that_do_not_apply_leave_the_text_alone() {
+        assert_eq!(run("splice", "a |b"), "a |b");
+        assert_eq!(run("slurp-forward", "(a |b)"), "(a |b)");
+        assert_eq!(run("barf-forward", "(|)"), "(|)");
+    }
+
+    #[test]
+    fn failed_edits_leave_the_original_text() {
+        let mut options: Options = serde_json::from_str("{}").unwrap();
+        options.cursor_line = Some(0);
+        options.cursor_x = Some(3);
+        options.positions = vec![Position { line_no: 0, x: 5 }];
+        let failed = parinfer::paren_mode("(a (b \"c)", &options);
+        let answer = unless_failed(failed, "(a b \"c\")", &options);
+        assert!(!answer.success);
+        assert_eq!(answer.text, "(a b \"c\")");
+        assert_eq!((answer.cursor_line, answer.cursor_x), (Some(0), Some(3)));
+        assert_eq!(answer.positions, options.positions);
+        assert_eq!(answer.error.map(|e| e.name), Some(ErrorName::UnclosedQuote));
+    }
+}
@@ -1,13 +1,23 @@
+use crate::analyze::{self, analysis_options};
 use crate::changes;
+use crate::documents;
+use crate::format;
+use crate::isolate::{self, static_paren};
+use crate::outline;
+use crate::parallel;
+use crate::paredit;
+use crate::paste;
+use crate::tab_stops;
 use crate::types::*;
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::mem;
 use unicode_segmentation::UnicodeSegmentation;
 use unicode_width::UnicodeWidthStr;
 
 // {{{1 Constants / Predicates
 
-fn match_paren(paren: &str) -> Option<&'static str> {
+pub(crate) fn match_paren(paren: &str) -> Option<&'static str> {
     match paren {
         "{" => Some("}"),
         "}" => Some("{"),
@@ -43,7 +53,7 @@ pub fn chomp_cr(text: &str) -> &str {
     }
 }
 
-fn split_lines(text: &str) -> Vec<&str> {
+pub(crate) fn split_lines(text: &str) -> Vec<&str> {
     text.split('\n').map(chomp_cr).collect()
 }
 
@@ -205,6 +215,7 @@ struct State<'a> {
     orig_text: &'a str,
     orig_cursor_x: Option<Column>,
     orig_cursor_line: Option<LineNumber>,
+    orig_positions: Vec<Position>,
 
     input_lines: Vec<&'a str>,
     input_line_no: LineNumber,
@@ -230,6 +241,7 @@ struct State<'a> {
     cursor_line: Option<LineNumber>,
     prev_cursor_x: Option<Column>,
     prev_cursor_line: Option<Column>,
+    positions: Vec<Position>,
 
     selection_start_line: Option<LineNumber>,
 
@@ -265,6 +277,19 @@ struct State<'a> {
     hy_bracket_tag: Vec<&'a str>,
     hy_bracket_tag_remaining: Vec<&'a str>,
 
+    trim_trailing_whitespace: bool,
+    tab_width: Option<Column>,
+    preserve_tabs: bool,
+    max_blank_lines: Option<usize>,
+    final_newline: bool,
+    // Blank lines outside strings, and whether each code line starts at the
+    // top level once indented, for collapsing blank lines between forms.
+    blank_lines: Vec<LineNumber>,
+    code_lines: Vec<(LineNumber, bool)>,
+
+    explain: bool,
+    trace: Vec<Decision>,
+
     error: Option<Error>,
     error_pos_cache: HashMap<ErrorName, Error>,
 }
@@ -300,6 +325,7 @@ fn get_initial_result<'a>(text: &'a str, options: &Options, mode: Mode, smart: b
         orig_text: text,
         orig_cursor_x: options.cursor_x,
         orig_cursor_line: options.cursor_line,
+        orig_positions: options.positions.clone(),
 
         input_lines: split_lines(text),
         input_line_no: 0,
@@ -324,6 +350,7 @@ fn get_initial_result<'a>(text: &'a str, options: &Options, mode: Mode, smart: b
         cursor_line: options.cursor_line,
         prev_cursor_x: options.prev_cursor_x,
         prev_cursor_line: options.prev_cursor_line,
+        positions: options.positions.clone(),
 
         selection_start_line: None,
 
@@ -359,6 +386,17 @@ fn get_initial_result<'a>(text: &'a str, options: &Options, mode: Mode, smart: b
         hy_bracket_tag: vec![],
         hy_bracket_tag_remaining: vec![],
 
+        trim_trailing_whitespace: options.trim_trailing_whitespace,
+        tab_width: options.tab_width.filter(|&width| width > 0),
+        preserve_tabs: options.preserve_tabs,
+        max_blank_lines: options.max_blank_lines,
+        final_newline: options.final_newline,
+        blank_lines: vec![],
+        code_lines: vec![],
+
+        explain: options.explain,
+        trace: vec![],
+
         error: None,
         error_pos_cache: HashMap::new(),
     }
@@ -368,7 +406,8 @@ fn get_initial_result<'a>(text: &'a str, options: &Options, mode: Mode, smart: b
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-fn error_message(error: ErrorName) -> &'static str {
+/// The message parinfer.js uses for each kind of error.
+pub fn error_message(error: ErrorName) -> &'static str {
     match error {
         ErrorName::QuoteDanger => "Quotes must balanced inside comment blocks.",
         ErrorName::EolBackslash => "Line cannot end in a hanging backslash.",
@@ -393,10 +432,50 @@ fn cache_error_pos(result: &mut State, name: ErrorName) {
         x: result.x,
         input_line_no: result.input_line_no,
         input_x: result.input_x,
+        extra: None,
     };
     result.error_pos_cache.insert(name, error);
 }
 
+fn error_extra(result: &State, name: ErrorName, position: &Error) -> ErrorExtra {
+    ErrorExtra {
+        name,
+        line_no: if result.partial_result {
+            position.line_no
+        } else {
+            position.input_line_no
+        },
+        x: if result.partial_result {
+            position.x
+        } else {
+            position.input_x
+        },
+        input_line_no: position.input_line_no,
+        input_x: position.input_x,
+    }
+}
+
+fn paren_position(paren: &Paren) -> Error {
+    Error {
+        line_no: paren.line_no,
+        x: paren.x,
+        input_line_no: paren.input_line_no,
+        input_x: paren.input_x,
+        ..Error::default()
+    }
+}
+
+fn end_of_text_position(result: &State) -> Error {
+    let input_line_no = result.input_lines.len() - 1;
+    Error {
+        line_no: result.line_no,
+        x: result.x,
+        input_line_no,
+        input_x: UnicodeWidthStr::width(result.input_lines[input_line_no]),
+        ..Error::default()
+    }
+}
+
 fn error(result: &mut State, name: ErrorName) -> Result<()> {
     let (line_no, x) = match (result.partial_result, result.error_pos_cache.get(&name)) {
         (true, Some(cache)) => (cache.line_no, cache.x),
@@ -412,29 +491,143 @@ fn error(result: &mut State, name: ErrorName) -> Result<()> {
         message: String::from(error_message(name)),
         input_line_no: result.input_line_no,
         input_x: result.input_x,
+        extra: None,
     };
 
-    if name == ErrorName::UnclosedParen {
-        if let Some(opener) = peek(&result.paren_stack, 0) {
-            e.line_no = if result.partial_result {
-                opener.line_no
-            } else {
-                opener.input_line_no
-            };
-            e.x = if result.partial_result {
-                opener.x
-            } else {
-                opener.input_x
+    match name {
+        ErrorName::UnmatchedCloseParen => {
+            // locate the open-paren that it should've matched
+            let opener = match result.error_pos_cache.get(&ErrorName::UnmatchedOpenParen) {
+                Some(cache) => Some(cache.clone()),
+                None => peek(&result.paren_stack, 0).map(paren_position),
             };
+            e.extra = opener
+                .map(|opener| error_extra(result, ErrorName::UnmatchedOpenParen, &opener));
+        }
+        ErrorName::UnclosedParen => {
+            if let Some(opener) = peek(&result.paren_stack, 0) {
+                e.line_no = if result.partial_result {
+                    opener.line_no
+                } else {
+                    opener.input_line_no
+                };
+                e.x = if result.partial_result {
+                    opener.x
+                } else {
+                    opener.input_x
+                };
+                e.extra = Some(error_extra(
+                    result,
+                    ErrorName::UnclosedParen,
+                    &end_of_text_position(result),
+                ));
+            }
         }
+        _ => (),
     }
 
     Err(e)
 }
 
+// {{{1 Explaining decisions
+
+// Records why something changed at `line_no` and `x`, when asked to.  The
+// message is only built then.
+fn explain(
+    result: &mut State<'_>,
+    name: DecisionName,
+    line_no: LineNumber,
+    x: Column,
+    message: impl FnOnce() -> String,
+) {
+    if result.explain {
+        result.trace.push(Decision {
+            name,
+            message: message(),
+            x,
+            line_no,
+        });
+    }
+}
+
+// The text of a line between two columns.
+fn line_slice<'a>(
+    result: &'a State<'_>,
+    line_no: LineNumber,
+    start: Column,
+    end: Column,
+) -> &'a str {
+    let line = &result.lines[line_no];
+    &line[column_byte_index(line, start)..column_byte_index(line, end)]
+}
+
+#[cfg(test)]
+#[test]
+fn decisions_are_explained() {
+    let trace = |mode: &str, text: &str, options: &str| {
+        let mut options: Options = serde_json::from_str(options).unwrap();
+        options.explain = true;
+        run_mode(mode, text, &options)
+            .trace
+            .iter()
+            .map(|decision| (decision.name, decision.line_no, decision.x))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        trace("indent", "(foo)\n  bar", "{}"),
+        vec![
+            (DecisionName::ParenTrailMoved, 0, 4),
+            (DecisionName::ParenTrailMoved, 1, 5)
+        ]
+    );
+    assert_eq!(
+        trace("indent", "(foo))", "{}"),
+        vec![(DecisionName::CloseParenRemoved, 0, 5)]
+    );
+    assert_eq!(
+        trace(
+            "indent",
+            "(foo (bar ))\n  baz",
+            r#"{"cursorLine": 0, "cursorX": 11}"#
+        )[0],
+        (DecisionName::ParenTrailHeld, 0, 9)
+    );
+    assert_eq!(
+        trace("paren", "(foo\n )bar", "{}"),
+        vec![
+            (DecisionName::CloseParenMoved, 1, 1),
+            (DecisionName::IndentClamped, 1, 0)
+        ]
+    );
+    assert_eq!(
+        trace("paren", "(a\n    )b", r#"{"cursorLine": 1, "cursorX": 4}"#),
+        vec![(DecisionName::CloseParenKept, 1, 4)]
+    );
+    assert_eq!(
+        trace("paren", "(let [x 1\n  y 2]\n  z)", "{}"),
+        vec![(DecisionName::IndentClamped, 1, 0)]
+    );
+    assert_eq!(trace("paren", "(let [x 1\n      y 2])", "{}"), vec![]);
+
+    let mut options: Options = serde_json::from_str("{}").unwrap();
+    assert!(indent_mode("(foo)\n  bar", &options).trace.is_empty());
+    options.explain = true;
+    let message = &indent_mode("(foo (bar)\nbaz", &options).trace[0].message;
+    assert_eq!(
+        message,
+        "paren trail became `))` because indentation 0 is not inside opener `(` at x 0"
+    );
+}
+
 // {{{1 String Operations
 
-fn column_byte_index(s: &str, x: usize) -> usize {
+/// The number of spaces `line` starts with.
+pub(crate) fn indentation(line: &str) -> Column {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+pub(crate) fn column_byte_index(s: &str, x: usize) -> usize {
     s.grapheme_indices(true)
         .scan(0, |column, (idx, ch)| {
             let start_column = *column;
@@ -491,7 +684,7 @@ fn repeat_string_works() {
     assert_eq!(repeat_string("", 5), "");
 }
 
-fn get_line_ending(text: &str) -> &'static str {
+pub(crate) fn get_line_ending(text: &str) -> &'static str {
     if text.chars().any(|ch| ch == '\r') {
         "\r\n"
     } else {
@@ -508,14 +701,21 @@ fn get_line_ending_works() {
 
 // {{{1 Line operations
 
-fn is_cursor_affected(result: &State<'_>, start: Column, end: Column) -> bool {
-    match result.cursor_x {
-        Some(x) if x == start && x == end => x == 0,
-        Some(x) => x >= end,
-        None => false,
+fn is_position_affected(x: Column, start: Column, end: Column) -> bool {
+    if x == start && x == end {
+        x == 0
+    } else {
+        x >= end
     }
 }
 
+fn is_cursor_affected(result: &State<'_>, start: Column, end: Column) -> bool {
+    result
+        .cursor_x
+        .map(|x| is_position_affected(x, start, end))
+        .unwrap_or(false)
+}
+
 fn shift_cursor_on_edit(
     result: &mut State<'_>,
     line_no: LineNumber,
@@ -532,6 +732,16 @@ fn shift_cursor_on_edit(
             result.cursor_x = Some(((cursor_x as Delta) + dx) as usize);
         }
     }
+
+    // Tracked positions move like the cursor, except that one inside the
+    // replaced text stays within its replacement.
+    for position in result.positions.iter_mut().filter(|p| p.line_no == line_no) {
+        if is_position_affected(position.x, start, end) {
+            position.x = ((position.x as Delta) + dx) as usize;
+        } else if start < position.x && position.x < end {
+            position.x = position.x.min(start + new_length);
+        }
+    }
 }
 
 fn replace_within_line(
@@ -637,7 +847,7 @@ fn peek_works() {
 
 // {{{1 Questions about characters
 
-fn is_close_paren(paren: &str) -> bool {
+pub(crate) fn is_close_paren(paren: &str) -> bool {
     matches!(paren, "}" | "]" | ")")
 }
 
@@ -694,6 +904,7 @@ fn check_cursor_holding(result: &State<'_>) -> Result<bool> {
                 input_x: 0,
                 line_no: 0,
                 message: String::new(),
+                extra: None,
             });
         }
     }
@@ -732,22 +943,18 @@ fn in_code_on_open_paren(result: &mut State<'_>) {
         children: vec![],
     };
 
-    if result.return_parens {
-        if let Some(parent) = result.paren_stack.last_mut() {
-            parent.children.push(opener.clone());
-        } else {
-            result.parens.push(opener.clone());
-        }
-    }
     result.paren_stack.push(opener);
     result.tracking_arg_tab_stop = TrackingArgTabStop::Space;
 }
 
 fn in_code_on_matched_close_paren(result: &mut State<'_>) -> Result<()> {
     let mut opener = (*peek(&result.paren_stack, 0).unwrap()).clone();
-    if result.return_parens {
-        set_closer(&mut opener);
-    }
+    let closed = if result.return_parens {
+        set_closer(result, &mut opener);
+        Some(opener.clone())
+    } else {
+        None
+    };
 
     result.paren_trail.end_x = Some(result.x + 1);
     result.paren_trail.openers.push(opener);
@@ -758,6 +965,15 @@ fn in_code_on_matched_close_paren(result: &mut State<'_>) -> Result<()> {
         let orig_openers = result.paren_trail.openers.clone();
         let x = result.x;
         let line_no = result.line_no;
+        let trail_x = orig_start_x.unwrap_or(x);
+        let message = || String::from("cursor held the paren trail while editing this line");
+        explain(
+            result,
+            DecisionName::ParenTrailHeld,
+            line_no,
+            trail_x,
+            message,
+        );
         reset_paren_trail(result, line_no, x + 1);
         result.paren_trail.clamped = ParenTrailClamped {
             start_x: orig_start_x,
@@ -766,6 +982,9 @@ fn in_code_on_matched_close_paren(result: &mut State<'_>) -> Result<()> {
         };
     }
     result.paren_stack.pop();
+    if let Some(closed) = closed {
+        add_to_paren_tree(result, closed);
+    }
     result.tracking_arg_tab_stop = TrackingArgTabStop::NotSearching;
 
     Ok(())
@@ -787,20 +1006,22 @@ fn in_code_on_unmatched_close_paren(result: &mut State<'_>) -> Result<()> {
                 .contains_key(&ErrorName::UnmatchedCloseParen)
             {
                 cache_error_pos(result, ErrorName::UnmatchedCloseParen);
-                if peek(&result.paren_stack, 0).is_some() {
-                    cache_error_pos(result, ErrorName::UnmatchedOpenParen);
-                    let opener = peek(&result.paren_stack, 0).unwrap();
-                    if let Some(err) = result
+                if let Some(opener) = peek(&result.paren_stack, 0) {
+                    let position = Error {
+                        name: ErrorName::UnmatchedOpenParen,
+                        ..paren_position(opener)
+                    };
+                    result
                         .error_pos_cache
-                        .get_mut(&ErrorName::UnmatchedOpenParen)
-                    {
-                        err.input_line_no = opener.input_line_no;
-                        err.input_x = opener.input_x;
-                    }
+                        .insert(ErrorName::UnmatchedOpenParen, position);
                 }
             }
         }
     }
+    let (line_no, x, ch) = (result.line_no, result.x, result.ch);
+    explain(result, DecisionName::CloseParenRemoved, line_no, x, || {
+        format!("close paren `{}` removed as unmatched", ch)
+    });
     result.ch = "";
 
     Ok(())
@@ -836,6 +1057,23 @@ fn after_backslash(result: &mut State<'_>) -> Result<()> {
 
 // {{{1 Character dispatch
 
+const SPACES: &str = "                                ";
+
+/// How wide a tab in code at column `x` becomes: enough to reach the next
+/// multiple of `tab_width`, or two.
+pub(crate) fn tab_width_at(x: Column, tab_width: Option<Column>) -> Column {
+    match tab_width.filter(|&width| width > 0) {
+        Some(width) => width - x % width,
+        None => 2,
+    }
+}
+
+// The spaces a tab in code becomes.
+fn expanded_tab(result: &State<'_>) -> &'static str {
+    let width = tab_width_at(result.x, result.tab_width);
+    &SPACES[..width.min(SPACES.len())]
+}
+
 fn on_context(result: &mut State<'_>) -> Result<()> {
     match (result.context, result.ch) {
         (In::Code, ch) if ch == result.comment_char => {
@@ -865,7 +1103,7 @@ fn on_context(result: &mut State<'_>) -> Result<()> {
             result.context = In::JanetLongStringPre { open_delim_len: 1 };
             cache_error_pos(result, ErrorName::UnclosedQuote);
         },
-        (In::Code, "\t") => { result.ch = "  "; },
+        (In::Code, "\t") if !result.preserve_tabs => { result.ch = expanded_tab(result); },
         (In::Code, _) => (),
         (In::Comment, ch) if result.string_delimiters.contains(&ch.to_string()) => in_comment_on_quote(result),
         (In::Comment, "|") if result.lisp_vline_symbols_enabled => in_comment_on_quote(result),
@@ -1072,6 +1310,25 @@ fn clamp_paren_trail_to_cursor(result: &mut State<'_>) {
             }
         }
 
+        if remove_count > 0 {
+            let line_no = result.line_no;
+            let held = String::from(line_slice(result, line_no, start_x, new_start_x));
+            let message = || {
+                format!(
+                    "cursor at x {} held `{}` of the paren trail in place",
+                    new_start_x,
+                    held.trim()
+                )
+            };
+            explain(
+                result,
+                DecisionName::ParenTrailHeld,
+                line_no,
+                start_x,
+                message,
+            );
+        }
+
         let openers = result.paren_trail.openers.clone();
 
         result.paren_trail.openers = openers[remove_count..].to_vec();
@@ -1285,12 +1542,15 @@ fn get_parent_opener_index(result: &mut State<'_>, indent_x: usize) -> usize {
 fn correct_paren_trail(result: &mut State<'_>, indent_x: usize) {
     let mut parens = String::new();
 
+    let mut outermost = None;
     let index = get_parent_opener_index(result, indent_x);
     for _ in 0..index {
         let mut opener = result.paren_stack.pop().unwrap();
         let close_ch = match_paren(opener.ch).unwrap();
+        outermost = Some((opener.ch, opener.x));
         if result.return_parens {
-            opener.closer = Some(Closer { trail: None });
+            set_closer(result, &mut opener);
+            add_to_paren_tree(result, opener.clone());
         }
         result.paren_trail.openers.push(opener);
         parens.push_str(close_ch);
@@ -1299,12 +1559,58 @@ fn correct_paren_trail(result: &mut State<'_>, indent_x: usize) {
     if let Some(line_no) = result.paren_trail.line_no {
         let start_x = result.paren_trail.start_x.unwrap();
         let end_x = result.paren_trail.end_x.unwrap();
+        if result.explain {
+            explain_paren_trail(result, line_no, start_x, end_x, &parens, outermost);
+        }
         replace_within_line(result, line_no, start_x, end_x, &parens[..]);
         result.paren_trail.end_x = result.paren_trail.start_x.map(|x| x + parens.len());
         remember_paren_trail(result);
     }
 }
 
+// Explains how indentation changed the paren trail from `start_x` to `end_x`
+// into `parens`: it gained closers up to the `outermost` opener the line
+// isn't inside, and lost those for openers the line is still inside.
+fn explain_paren_trail(
+    result: &mut State<'_>,
+    line_no: LineNumber,
+    start_x: Column,
+    end_x: Column,
+    parens: &str,
+    outermost: Option<(&str, Column)>,
+) {
+    let indent_x = result.x;
+    let old = line_slice(result, line_no, start_x, end_x)
+        .trim()
+        .to_string();
+    if old == parens {
+        return;
+    }
+    let at_end = result.line_no >= result.lines.len();
+    let message = match (outermost, peek(&result.paren_stack, 0)) {
+        (Some((ch, x)), _) if at_end => format!(
+            "paren trail became `{}` to close opener `{}` at x {} at the end of the text",
+            parens, ch, x
+        ),
+        (Some((ch, x)), _) => format!(
+            "paren trail became `{}` because indentation {} is not inside opener `{}` at x {}",
+            parens, indent_x, ch, x
+        ),
+        (None, Some(parent)) => format!(
+            "paren trail `{}` removed because indentation {} is inside opener `{}` at x {}",
+            old, indent_x, parent.ch, parent.x
+        ),
+        (None, None) => format!("paren trail `{}` removed", old),
+    };
+    explain(
+        result,
+        DecisionName::ParenTrailMoved,
+        line_no,
+        start_x,
+        || message,
+    );
+}
+
 fn clean_paren_trail(result: &mut State<'_>) {
     let start_x = result.paren_trail.start_x;
     let end_x = result.paren_trail.end_x;
@@ -1344,15 +1650,31 @@ fn clean_paren_trail(result: &mut State<'_>) {
     }
 }
 
-fn set_closer(opener: &mut Paren<'_>) {
-    opener.closer = Some(Closer { trail: None })
+// The input position of a closer parinfer inserts is where it was inferred.
+fn set_closer(result: &State<'_>, opener: &mut Paren<'_>) {
+    opener.closer = Some(Closer {
+        ch: match_paren(opener.ch).unwrap(),
+        input_line_no: result.input_line_no,
+        input_x: result.input_x,
+        trail: None,
+    })
+}
+
+// Closed parens hang under the opener still on the stack, so each one
+// arrives with all of its own children.
+fn add_to_paren_tree<'a>(result: &mut State<'a>, opener: Paren<'a>) {
+    match result.paren_stack.last_mut() {
+        Some(parent) => parent.children.push(opener),
+        None => result.parens.push(opener),
+    }
 }
 
 fn append_paren_trail(result: &mut State<'_>) {
     let mut opener = result.paren_stack.pop().unwrap().clone();
     let close_ch = match_paren(opener.ch).unwrap();
     if result.return_parens {
-        set_closer(&mut opener);
+        set_closer(result, &mut opener);
+        add_to_paren_tree(result, opener.clone());
     }
 
     set_max_indent(result, &opener);
@@ -1480,6 +1802,45 @@ fn should_add_opener_indent<'a>(result: &State<'a>, opener: &Paren<'a>) -> bool
     opener.indent_delta != result.indent_delta
 }
 
+// A line moves along with the opener it's inside.
+fn explain_indent_shift(result: &mut State<'_>, delta: Delta) {
+    if delta == 0 {
+        return;
+    }
+    if let Some(opener) = peek(&result.paren_stack, 0) {
+        let (line_no, ch, x) = (result.line_no, opener.ch, opener.x);
+        explain(result, DecisionName::IndentShifted, line_no, 0, || {
+            format!(
+                "indentation shifted by {} with opener `{}` at x {}",
+                delta, ch, x
+            )
+        });
+    }
+}
+
+// PAREN MODE: a line is kept inside its opener, and out of lists closed on
+// the lines above it.
+fn explain_indent_clamp(result: &mut State<'_>, indent: Delta, new_indent: Delta) {
+    let line_no = result.line_no;
+    let opener = peek(&result.paren_stack, 0).map(|opener| (opener.ch, opener.x));
+    explain(
+        result,
+        DecisionName::IndentClamped,
+        line_no,
+        0,
+        || match opener {
+            Some((ch, x)) if new_indent > indent => format!(
+                "indentation {} raised to {} to stay inside opener `{}` at x {}",
+                indent, new_indent, ch, x
+            ),
+            _ => format!(
+                "indentation {} clamped by max indent {} to stay out of lists closed above",
+                indent, new_indent
+            ),
+        },
+    );
+}
+
 fn correct_indent(result: &mut State<'_>) {
     let orig_indent = result.x as Delta;
     let mut new_indent = orig_indent as Delta;
@@ -1494,9 +1855,15 @@ fn correct_indent(result: &mut State<'_>) {
         }
     }
 
+    let shifted_indent = new_indent;
     new_indent = clamp(new_indent, Some(min_indent as Delta), max_indent);
 
     if new_indent != orig_indent {
+        if new_indent == shifted_indent {
+            explain_indent_shift(result, new_indent - orig_indent);
+        } else {
+            explain_indent_clamp(result, shifted_indent, new_indent);
+        }
         add_indent(result, new_indent - orig_indent);
     }
 }
@@ -1522,12 +1889,16 @@ fn on_indent(result: &mut State<'_>) -> Result<()> {
             };
 
             if let Some(adjust) = to_add {
+                explain_indent_shift(result, adjust);
                 add_indent(result, adjust);
             }
         }
         Mode::Paren => correct_indent(result),
     }
 
+    let at_top_level = result.paren_stack.is_empty();
+    record_code_line(result, at_top_level);
+
     Ok(())
 }
 
@@ -1543,6 +1914,13 @@ fn check_leading_close_paren(result: &mut State<'_>) -> Result<()> {
     Ok(())
 }
 
+fn explain_leading_close_paren(result: &mut State<'_>, name: DecisionName, why: &str) {
+    let (line_no, x, ch) = (result.line_no, result.x, result.ch);
+    explain(result, name, line_no, x, || {
+        format!("leading close paren `{}` {}", ch, why)
+    });
+}
+
 fn on_leading_close_paren(result: &mut State<'_>) -> Result<()> {
     match result.mode {
         Mode::Indent => {
@@ -1557,11 +1935,21 @@ fn on_leading_close_paren(result: &mut State<'_>) -> Result<()> {
                     cache_error_pos(result, ErrorName::LeadingCloseParen);
                 }
             }
+            explain_leading_close_paren(
+                result,
+                DecisionName::CloseParenRemoved,
+                "removed because indentation decides where lists close",
+            );
             result.skip_char = true;
         }
         Mode::Paren => {
             if !is_valid_close_paren(&result.paren_stack, result.ch) {
                 if result.smart {
+                    explain_leading_close_paren(
+                        result,
+                        DecisionName::CloseParenRemoved,
+                        "removed as unmatched",
+                    );
                     result.skip_char = true;
                 } else {
                     error(result, ErrorName::UnmatchedCloseParen)?;
@@ -1572,11 +1960,21 @@ fn on_leading_close_paren(result: &mut State<'_>) -> Result<()> {
                 Some(result.x),
                 result.line_no,
             ) {
+                explain_leading_close_paren(
+                    result,
+                    DecisionName::CloseParenKept,
+                    "kept in place because the cursor is before it",
+                );
                 let line_no = result.line_no;
                 let x = result.x;
                 reset_paren_trail(result, line_no, x);
                 on_indent(result)?;
             } else {
+                explain_leading_close_paren(
+                    result,
+                    DecisionName::CloseParenMoved,
+                    "moved to the end of the previous paren trail",
+                );
                 append_paren_trail(result);
                 result.skip_char = true;
             }
@@ -1622,6 +2020,7 @@ fn on_comment_line(result: &mut State<'_>) {
 
 fn check_indent(result: &mut State<'_>) -> Result<()> {
     if is_close_paren(result.ch) {
+        record_code_line(result, false);
         on_leading_close_paren(result)?;
     } else if result.ch == result.comment_char {
         // comments don't count as indentation points
@@ -1676,6 +2075,161 @@ fn set_tab_stops(result: &mut State<'_>) {
     }
 }
 
+// {{{1 Whitespace normalization
+
+fn record_code_line(result: &mut State<'_>, at_top_level: bool) {
+    if result.max_blank_lines.is_some() {
+        result.code_lines.push((result.line_no, at_top_level));
+    }
+}
+
+// Trims the line's trailing whitespace, unless the line ends in a string or
+// holds the cursor, which may be about to type after it.
+fn trim_trailing_whitespace(result: &mut State<'_>) {
+    if !result.trim_trailing_whitespace
+        || result.is_in_stringish()
+        || result.cursor_line == Some(result.line_no)
+    {
+        return;
+    }
+    let line = &result.lines[result.line_no];
+    let trimmed = line.trim_end_matches([' ', '\t']);
+    if trimmed.len() < line.len() {
+        let start = UnicodeWidthStr::width(trimmed);
+        let end = UnicodeWidthStr::width(&line[..]);
+        let line_no = result.line_no;
+        replace_within_line(result, line_no, start, end, "");
+    }
+}
+
+// Removes blank lines beyond `max_blank_lines` in each run of them between
+// top-level forms, moving everything after them up.
+fn collapse_blank_lines(result: &mut State<'_>) {
+    let Some(max) = result.max_blank_lines else {
+        return;
+    };
+    let at_top_level: HashMap<LineNumber, bool> = result.code_lines.iter().copied().collect();
+    let mut removed = vec![false; result.lines.len()];
+    let mut next_at_top_level = true;
+    let mut run: Vec<LineNumber> = vec![];
+    let mut blank_lines = result.blank_lines.iter().rev().peekable();
+    for line_no in (0..result.lines.len()).rev() {
+        if let Some(&top_level) = at_top_level.get(&line_no) {
+            next_at_top_level = top_level;
+        }
+        if blank_lines.next_if(|&&blank| blank == line_no).is_some() && next_at_top_level {
+            run.push(line_no);
+            continue;
+        }
+        for &line_no in run.iter().skip(max) {
+            removed[line_no] = true;
+        }
+        run.clear();
+    }
+    for &line_no in run.iter().skip(max) {
+        removed[line_no] = true;
+    }
+    if !removed.contains(&true) {
+        return;
+    }
+
+    // Lines after a removed one move up; anything on a removed line moves to
+    // the start of the line that takes its place.
+    let mut new_line_nos = Vec::with_capacity(removed.len());
+    let mut count = 0;
+    for &is_removed in &removed {
+        new_line_nos.push(count);
+        if !is_removed {
+            count += 1;
+        }
+    }
+    let lines = mem::take(&mut result.lines);
+    result.lines = lines
+        .into_iter()
+        .zip(&removed)
+        .filter(|(_, &is_removed)| !is_removed)
+        .map(|(line, _)| line)
+        .collect();
+    if let Some(line_no) = result.cursor_line {
+        if removed.get(line_no) == Some(&true) {
+            result.cursor_x = Some(0);
+        }
+        result.cursor_line = new_line_nos.get(line_no).copied().or(Some(line_no));
+    }
+    for position in result.positions.iter_mut() {
+        if let Some(&new_line_no) = new_line_nos.get(position.line_no) {
+            if removed[position.line_no] {
+                position.x = 0;
+            }
+            position.line_no = new_line_no;
+        }
+    }
+    for paren_trail in result.paren_trails.iter_mut() {
+        paren_trail.line_no = new_line_nos[paren_trail.line_no];
+    }
+    for tab_stop in result.tab_stops.iter_mut() {
+        tab_stop.line_no = new_line_nos[tab_stop.line_no];
+    }
+    for decision in result.trace.iter_mut() {
+        decision.line_no = new_line_nos[decision.line_no];
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn whitespace_normalization() {
+    let normalize = |text: &str, options: &str| {
+        let options: Options = serde_json::from_str(options).unwrap();
+        let answer = paren_mode(text, &options);
+        (
+            answer.text.into_owned(),
+            answer.cursor_line.zip(answer.cursor_x),
+            answer
+                .positions
+                .iter()
+                .map(|position| (position.line_no, position.x))
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    let trim = r#"{"trimTrailingWhitespace": true, "cursorLine": 2, "cursorX": 4}"#;
+    assert_eq!(
+        normalize("(foo  \n  \"a  \n b\"  \n  c ; d \t\n  e)  ", trim).0,
+        "(foo\n  \"a  \n b\"  \n  c ; d\n  e)"
+    );
+    assert_eq!(normalize("(a\tb)", "{}").0, "(a  b)");
+    assert_eq!(normalize("(a\tb)", r#"{"tabWidth": 4}"#).0, "(a  b)");
+    assert_eq!(normalize("(ab\tc)", r#"{"tabWidth": 4}"#).0, "(ab c)");
+    assert_eq!(
+        normalize("(a\tb \"\t\")", r#"{"preserveTabs": true}"#).0,
+        "(a\tb \"\t\")"
+    );
+
+    let blank_lines = r#"{
+        "maxBlankLines": 1,
+        "cursorLine": 7,
+        "cursorX": 1,
+        "positions": [{"lineNo": 2, "x": 0}, {"lineNo": 5, "x": 2}]
+    }"#;
+    assert_eq!(
+        normalize("(a)\n\n\n\n(b\n\n\n  c)\n(d)\n\n", blank_lines),
+        (
+            String::from("(a)\n\n(b\n\n\n  c)\n(d)\n\n"),
+            Some((5, 1)),
+            vec![(1, 0), (3, 2)]
+        )
+    );
+    assert_eq!(
+        normalize("(a \"\n\n\n\")", r#"{"maxBlankLines": 0}"#).0,
+        "(a \"\n\n\n\")"
+    );
+    assert_eq!(normalize("(a)", r#"{"finalNewline": true}"#).0, "(a)\n");
+    assert_eq!(
+        normalize("(a)\r\n", r#"{"finalNewline": true}"#).0,
+        "(a)\r\n"
+    );
+}
+
 // {{{1 High-level processing functions
 
 fn process_char<'a>(result: &mut State<'a>, ch: &'a str) -> Result<()> {
@@ -1704,6 +2258,15 @@ fn process_char<'a>(result: &mut State<'a>, ch: &'a str) -> Result<()> {
 fn process_line(result: &mut State<'_>, line_no: usize) -> Result<()> {
     init_line(result);
     result.lines.push(Cow::from(result.input_lines[line_no]));
+    // An empty last line only ends the one before it.
+    let is_end = line_no + 1 == result.input_lines.len() && result.input_lines[line_no].is_empty();
+    if result.max_blank_lines.is_some()
+        && !result.is_in_stringish()
+        && !is_end
+        && result.input_lines[line_no].trim().is_empty()
+    {
+        result.blank_lines.push(result.line_no);
+    }
 
     set_tab_stops(result);
 
@@ -1728,6 +2291,7 @@ fn process_line(result: &mut State<'_>, line_no: usize) -> Result<()> {
     if Some(result.line_no) == result.paren_trail.line_no {
         finish_new_paren_trail(result);
     }
+    trim_trailing_whitespace(result);
 
     Ok(())
 }
@@ -1782,12 +2346,60 @@ fn process_text<'a>(text: &'a str, options: &Options, mode: Mode, smart: bool) -
             process_error(&mut result, e);
             result
         }
-        _ => result,
+        _ => {
+            collapse_blank_lines(&mut result);
+            if result.final_newline && result.lines.last().is_some_and(|line| !line.is_empty()) {
+                result.lines.push(Cow::from(""));
+            }
+            result
+        }
     }
 }
 
+/// Parses `text` with paren mode and returns its top-level lists, each with
+/// its closer and its nested lists in `children`, and for each line whether
+/// it starts inside a string or a block comment.
+pub fn paren_tree<'a>(text: &'a str, options: &Options) -> Result<(Vec<Paren<'a>>, Vec<bool>)> {
+    let mut result = get_initial_result(text, &analysis_options(options), Mode::Paren, false);
+    result.return_parens = true;
+
+    let mut stringish = Vec::with_capacity(result.input_lines.len());
+    for i in 0..result.input_lines.len() {
+        result.input_line_no = i;
+        stringish.push(result.is_in_stringish());
+        process_line(&mut result, i)?;
+    }
+    finalize_result(&mut result)?;
+    Ok((result.parens, stringish))
+}
+
 // {{{1 Public API
 
+// The answer for text made inside the library, which it can't borrow from.
+pub(crate) fn owned_answer<'a>(answer: Answer<'_>) -> Answer<'a> {
+    Answer {
+        text: Cow::from(answer.text.into_owned()),
+        success: answer.success,
+        error: answer.error,
+        cursor_x: answer.cursor_x,
+        cursor_line: answer.cursor_line,
+        tab_stops: answer
+            .tab_stops
+            .iter()
+            .map(|tab_stop| TabStop {
+                ch: static_paren(tab_stop.ch),
+                ..*tab_stop
+            })
+            .collect(),
+        paren_trails: answer.paren_trails,
+        parens: vec![],
+        errors: answer.errors,
+        outline: answer.outline,
+        positions: answer.positions,
+        trace: answer.trace,
+    }
+}
+
 fn public_result(result: State<'_>) -> Answer<'_> {
     let line_ending = get_line_ending(result.orig_text);
     if result.success {
@@ -1800,6 +2412,10 @@ fn public_result(result: State<'_>) -> Answer<'_> {
             paren_trails: result.paren_trails,
             parens: result.parens,
             error: None,
+            errors: vec![],
+            outline: None,
+            positions: result.positions,
+            trace: result.trace,
         }
     } else {
         Answer {
@@ -1823,6 +2439,18 @@ fn public_result(result: State<'_>) -> Answer<'_> {
             tab_stops: result.tab_stops,
             error: result.error,
             parens: result.parens,
+            errors: vec![],
+            outline: None,
+            positions: if result.partial_result {
+                result.positions
+            } else {
+                result.orig_positions
+            },
+            trace: if result.partial_result {
+                result.trace
+            } else {
+                vec![]
+            },
         }
     }
 }
@@ -1840,25 +2468,132 @@ pub fn smart_mode<'a>(text: &'a str, options: &Options) -> Answer<'a> {
     public_result(process_text(text, options, Mode::Indent, smart))
 }
 
-pub fn process(request: &Request) -> Answer {
+pub(crate) fn run_mode<'a>(mode: &str, text: &'a str, options: &Options) -> Answer<'a> {
+    if let Some(document) = options.document {
+        return documents::document_mode(mode, text, document, options);
+    }
+    if let Some((text, options)) = paste::normalize(text, options) {
+        return owned_answer(run_mode(mode, &text, &options));
+    }
+
+    let mut answer = if mode == "paren" {
+        paren_mode(text, options)
+    } else if mode == "indent" {
+        parallel::parallel_workers(text, options)
+            .and_then(|workers| parallel::parallel_indent_mode(text, options, workers))
+            .unwrap_or_else(|| indent_mode(text, options))
+    } else if mode == "smart" {
+        smart_mode(text, options)
+    } else if mode == "analyze" {
+        return analyze::analyze_mode(text, options);
+    } else if mode == "outline" {
+        return outline::outline_mode(text, options);
+    } else if mode == "format" {
+        return format::format_mode(text, options);
+    } else if let Ok(operation) = mode.parse() {
+        return paredit::edit_mode(operation, text, options);
+    } else if let Ok(direction) = mode.parse() {
+        return tab_stops::tab_mode(direction, text, options);
+    } else {
+        return Answer::from(Error {
+            message: String::from("Bad value specified for `mode`"),
+            ..Error::default()
+        });
+    };
+
+    if !answer.success && options.isolate_forms {
+        answer = isolate::process_isolated_forms(mode, text, options);
+    }
+    if options.report_all_errors {
+        add_errors(&mut answer.errors, analyze::analyze(text, options));
+    }
+    answer
+}
+
+// Adds the errors not already in `errors`, keeping them in text order.
+fn add_errors(errors: &mut Vec<Error>, more: Vec<Error>) {
+    for error in more {
+        let key = (error.name, error.line_no, error.x);
+        if !errors.iter().any(|e| (e.name, e.line_no, e.x) == key) {
+            errors.push(error);
+        }
+    }
+    errors.sort_by_key(|error| (error.line_no, error.x));
+}
+
+pub fn process(request: &Request) -> Answer<'_> {
     let mut options = request.options.clone();
 
     if let Some(ref prev_text) = request.options.prev_text {
         options.changes = changes::compute_text_changes(prev_text, &request.text);
     }
 
-    if request.mode == "paren" {
-        paren_mode(&request.text, &options)
-    } else if request.mode == "indent" {
-        indent_mode(&request.text, &options)
-    } else if request.mode == "smart" {
-        smart_mode(&request.text, &options)
-    } else {
-        Answer::from(Error {
-            message: String::from("Bad value specified for `mode`"),
-            ..Error::default()
-        })
-    }
+    run_mode(&request.mode, &request.text, &options)
+}
+
+#[cfg(test)]
+#[test]
+fn positions_follow_edits() {
+    let moved = |mode: &str,
+                 text: &str,
+                 cursor: Option<(LineNumber, Column)>,
+                 positions: &[(LineNumber, Column)]| {
+        let mut options: Options = serde_json::from_str("{}").unwrap();
+        options.cursor_line = cursor.map(|(line_no, _)| line_no);
+        options.cursor_x = cursor.map(|(_, x)| x);
+        options.isolate_forms = true;
+        options.positions = positions
+            .iter()
+            .map(|&(line_no, x)| Position { x, line_no })
+            .collect();
+        let request = Request {
+            mode: String::from(mode),
+            text: String::from(text),
+            options,
+        };
+        process(&request)
+            .positions
+            .into_iter()
+            .map(|position| (position.line_no, position.x))
+            .collect::<Vec<_>>()
+    };
+
+    // indentation, which carries a position at the start of the line along
+    assert_eq!(
+        moved("paren", "(foo\nbar)", None, &[(1, 0), (1, 2), (0, 1)]),
+        vec![(1, 1), (1, 3), (0, 1)]
+    );
+    // paren trails
+    assert_eq!(
+        moved("indent", "(foo\nbar)", None, &[(0, 4), (1, 3), (1, 4)]),
+        vec![(0, 4), (1, 3), (1, 3)]
+    );
+    // a position in removed text stays inside what replaced it
+    assert_eq!(
+        moved("indent", "(foo ) ;x", None, &[(0, 5), (0, 9)]),
+        vec![(0, 5), (0, 8)]
+    );
+    // failures leave the text, and the positions, alone
+    assert_eq!(moved("paren", "(foo\n\"bar", None, &[(1, 0)]), vec![(1, 0)]);
+    // isolated forms
+    assert_eq!(
+        moved("indent", "(a b)\n  c\n(d\n\"e", None, &[(0, 5), (3, 1)]),
+        vec![(0, 4), (3, 1)]
+    );
+    // structural edits and tab stops
+    assert_eq!(
+        moved("raise", "(a (b c))", Some((0, 6)), &[(0, 4), (0, 9)]),
+        vec![(0, 3), (0, 5)]
+    );
+    assert_eq!(
+        moved(
+            "indent-forward",
+            "(let [x 1]\n  y)",
+            Some((1, 2)),
+            &[(1, 3)]
+        ),
+        vec![(1, 6)]
+    );
 }
 
 // This is like the process function above, but uses a reference counted version of Request
@@ -1870,16 +2605,5 @@ pub fn rc_process(request: &SharedRequest) -> Answer<'_> {
         options.changes = changes::compute_text_changes(prev_text, &request.text);
     }
 
-    if request.mode == "paren" {
-        paren_mode(&request.text, &options)
-    } else if request.mode == "indent" {
-        indent_mode(&request.text, &options)
-    } else if request.mode == "smart" {
-        smart_mode(&request.text, &options)
-    } else {
-        Answer::from(Error {
-            message: String::from("Bad value specified for `mode`"),
-            ..Error::default()
-        })
-    }
+    run_mode(&request.mode, &request.text, &options)
 }
@@ -0,0 +1,458 @@
+//! Reindenting pasted text to the column it was pasted at.
+//!
+//! Copied text usually starts at a form, so its first line has lost its
+//! indentation while the others kept theirs.  Pasted somewhere else, those
+//! lines would be indented relative to the wrong column and smart mode would
+//! move parens to match.  Instead we work out which column the first line
+//! came from and shift the others by the same amount as it.
+
+use crate::parinfer::{self, column_byte_index, indentation};
+use crate::types::*;
+use unicode_width::UnicodeWidthStr;
+
+// What a line of pasted text starts in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Parent {
+    // No list: it's a sibling of the first line's forms.
+    TopLevel,
+    // A list opened with `ch` on the first line at `x`, whose first
+    // argument, if it is on that line too, is at `arg_x`.
+    FirstLine {
+        ch: char,
+        x: Column,
+        arg_x: Option<Column>,
+    },
+    // A list opened on a later line.
+    Other,
+    // A string, whose contents mustn't be touched.
+    String,
+}
+
+struct Opener {
+    ch: char,
+    line_no: LineNumber,
+    x: Column,
+    forms: usize,
+    arg_x: Option<Column>,
+}
+
+fn is_blank(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+// Counts a form starting at `x` on `line_no` towards its list's arguments.
+fn start_form(stack: &mut [Opener], line_no: LineNumber, x: Column) {
+    if let Some(opener) = stack.last_mut() {
+        if opener.line_no == 0 && line_no == 0 {
+            opener.forms += 1;
+            if opener.forms == 2 {
+                opener.arg_x = Some(x);
+            }
+        }
+    }
+}
+
+// What each line after the first starts in.
+fn parents(lines: &[&str], options: &Options) -> Vec<Parent> {
+    let mut stack: Vec<Opener> = vec![];
+    let mut in_string: Option<&str> = None;
+    let mut parents = vec![];
+    for (line_no, line) in lines.iter().enumerate() {
+        if line_no > 0 {
+            parents.push(match (in_string, stack.last()) {
+                (Some(_), _) => Parent::String,
+                (None, None) => Parent::TopLevel,
+                (None, Some(opener)) if opener.line_no == 0 => Parent::FirstLine {
+                    ch: opener.ch,
+                    x: opener.x,
+                    arg_x: opener.arg_x,
+                },
+                (None, Some(_)) => Parent::Other,
+            });
+        }
+
+        let mut in_atom = false;
+        let mut i = 0;
+        while let Some(ch) = line[i..].chars().next() {
+            let rest = &line[i..];
+            let x = UnicodeWidthStr::width(&line[..i]);
+            let mut len = ch.len_utf8();
+            if let Some(delimiter) = in_string {
+                if ch == '\\' {
+                    len += rest[len..].chars().next().map_or(0, char::len_utf8);
+                } else if rest.starts_with(delimiter) {
+                    len = delimiter.len();
+                    in_string = None;
+                }
+            } else if ch == options.comment_char {
+                break;
+            } else if ch.is_whitespace() {
+                in_atom = false;
+            } else if "([{".contains(ch) {
+                start_form(&mut stack, line_no, x);
+                stack.push(Opener {
+                    ch,
+                    line_no,
+                    x,
+                    forms: 0,
+                    arg_x: None,
+                });
+                in_atom = false;
+            } else if ")]}".contains(ch) {
+                stack.pop();
+                in_atom = false;
+            } else {
+                if !in_atom {
+                    start_form(&mut stack, line_no, x);
+                }
+                if let Some(delimiter) = options
+                    .string_delimiters
+                    .iter()
+                    .find(|d| !d.is_empty() && rest.starts_with(d.as_str()))
+                {
+                    len = delimiter.len();
+                    in_string = Some(delimiter);
+                    in_atom = false;
+                } else {
+                    if ch == '\\' {
+                        len += rest[len..].chars().next().map_or(0, char::len_utf8);
+                    }
+                    in_atom = true;
+                }
+            }
+            i += len;
+        }
+    }
+    parents
+}
+
+// Guesses the column the first line was copied from, in the indentation of
+// the others, when the editor doesn't say.  Its siblings tell us exactly.
+// Otherwise each child of a list on the first line suggests columns that
+// would have lined it up with the list's first or second form or given it a
+// two-space body indent, and we take the one suggested most, keeping every
+// child inside its list.  Ties go to the usual style: bodies for parens,
+// first forms for the rest.
+fn source_column(lines: &[&str], parents: &[Parent]) -> Column {
+    if indentation(lines[0]) > 0 {
+        // Whole lines were copied.
+        return 0;
+    }
+    let lines: Vec<(Column, Parent)> = lines[1..]
+        .iter()
+        .zip(parents)
+        .filter(|(line, _)| !is_blank(line))
+        .map(|(line, parent)| (indentation(line), *parent))
+        .collect();
+    if let Some(column) = lines
+        .iter()
+        .filter(|(_, parent)| *parent == Parent::TopLevel)
+        .map(|(indent, _)| *indent)
+        .min()
+    {
+        return column;
+    }
+
+    let children: Vec<(Column, char, Column, Option<Column>)> = lines
+        .iter()
+        .filter_map(|(indent, parent)| match *parent {
+            Parent::FirstLine { ch, x, arg_x } => Some((*indent, ch, x, arg_x)),
+            _ => None,
+        })
+        .collect();
+    let Some(max) = children
+        .iter()
+        .map(|&(indent, _, x, _)| indent.saturating_sub(x + 1))
+        .min()
+    else {
+        return 0;
+    };
+    let candidates: Vec<Column> = children
+        .iter()
+        .flat_map(|&(indent, ch, x, arg_x)| {
+            let body = indent.checked_sub(x + 2);
+            let first = indent.checked_sub(x + 1);
+            let arg = arg_x.and_then(|arg_x| indent.checked_sub(arg_x));
+            if ch == '(' {
+                [body, arg, first]
+            } else {
+                [first, body, arg]
+            }
+        })
+        .flatten()
+        .filter(|&column| column <= max)
+        .collect();
+    let votes = |column: Column| candidates.iter().filter(|&&c| c == column).count();
+    candidates
+        .iter()
+        .fold(None, |best, &column| match best {
+            Some(best) if votes(best) >= votes(column) => Some(best),
+            _ => Some(column),
+        })
+        .unwrap_or(max)
+}
+
+/// Reindents `snippet`, pasted with its first line at column `x`, so that
+/// its other lines keep their indentation relative to the first.  That was
+/// at `source_x` where it was copied from, or if that isn't known, wherever
+/// the snippet's structure suggests.  Lines inside strings are left alone.
+pub fn reindent(snippet: &str, x: Column, source_x: Option<Column>, options: &Options) -> String {
+    let lines: Vec<&str> = snippet.split('\n').collect();
+    if lines.len() == 1 {
+        return String::from(snippet);
+    }
+    let parents = parents(&lines, options);
+    let from = source_x.unwrap_or_else(|| source_column(&lines, &parents));
+    let mut output = vec![String::from(lines[0])];
+    for (line, parent) in lines[1..].iter().zip(parents) {
+        if parent == Parent::String || is_blank(line) {
+            output.push(String::from(*line));
+            continue;
+        }
+        let indent = indentation(line);
+        let new_indent = (indent + x).saturating_sub(from);
+        output.push(format!("{}{}", " ".repeat(new_indent), &line[indent..]));
+    }
+    output.join("\n")
+}
+
+fn shift(position: &mut Column, old_indent: Column, new_indent: Column) {
+    *position = (*position + new_indent).saturating_sub(old_indent);
+}
+
+/// Reindents the text of each paste in `options.changes`, which `text`
+/// already contains.  Returns the new text and the options to run over it,
+/// with the pastes' new text, the cursor and tracked positions updated, or
+/// `None` if there are no pastes.
+pub(crate) fn normalize(text: &str, options: &Options) -> Option<(String, Options)> {
+    if options
+        .changes
+        .iter()
+        .all(|change| change.kind != ChangeKind::Paste)
+    {
+        return None;
+    }
+    let mut lines: Vec<String> = text.split('\n').map(String::from).collect();
+    let mut result = options.clone();
+    for change in result.changes.iter_mut() {
+        if change.kind != ChangeKind::Paste {
+            continue;
+        }
+        change.kind = ChangeKind::Edit;
+        let reindented = reindent(&change.new_text, change.x, change.source_x, options);
+        let pasted: Vec<(LineNumber, &str, &str)> = change
+            .new_text
+            .split('\n')
+            .zip(reindented.split('\n'))
+            .enumerate()
+            .skip(1)
+            .map(|(i, (old, new))| (change.line_no + i, old, new))
+            .collect();
+        let in_text = pasted.iter().all(|(line_no, old, _)| {
+            lines
+                .get(*line_no)
+                .is_some_and(|line| line.starts_with(old))
+        });
+        if !in_text {
+            continue;
+        }
+        for &(line_no, old, new) in &pasted {
+            let (old_indent, new_indent) = (indentation(old), indentation(new));
+            lines[line_no].replace_range(..old.len(), new);
+            if result.cursor_line == Some(line_no) {
+                if let Some(cursor_x) = result.cursor_x.as_mut() {
+                    shift(cursor_x, old_indent, new_indent);
+                }
+            }
+            for position in result.positions.iter_mut() {
+                if position.line_no == line_no {
+                    shift(&mut position.x, old_indent, new_indent);
+                }
+            }
+        }
+        change.new_text = reindented;
+    }
+    Some((lines.join("\n"), result))
+}
+
+/// Pastes `snippet` into `text` at `line_no` and `x`, reindents its lines
+/// to that column and runs smart mode, leaving the cursor after it.  This is
+/// a `"type": "paste"` change for callers which haven't pasted it yet.
+#[allow(dead_code)]
+pub fn paste<'a>(
+    text: &str,
+    line_no: LineNumber,
+    x: Column,
+    snippet: &str,
+    options: &Options,
+) -> Answer<'a> {
+    let mut lines: Vec<&str> = text.split('\n').collect();
+    let line_no = line_no.min(lines.len() - 1);
+    let line = lines[line_no];
+    let (before, after) = line.split_at(column_byte_index(line, x));
+    let x = UnicodeWidthStr::width(before);
+    let pasted = format!("{}{}{}", before, snippet, after);
+    lines[line_no] = &pasted;
+    let pasted_text = lines.join("\n");
+
+    let added_lines = snippet.matches('\n').count();
+    let last_line = snippet.rsplit('\n').next().unwrap_or("");
+    let end_x = UnicodeWidthStr::width(last_line) + if added_lines == 0 { x } else { 0 };
+    let positions = options
+        .positions
+        .iter()
+        .map(|&position| {
+            if position.line_no == line_no && position.x >= x {
+                Position {
+                    line_no: line_no + added_lines,
+                    x: position.x - x + end_x,
+                }
+            } else if position.line_no > line_no {
+                Position {
+                    line_no: position.line_no + added_lines,
+                    ..position
+                }
+            } else {
+                position
+            }
+        })
+        .collect();
+    let options = Options {
+        cursor_x: Some(end_x),
+        cursor_line: Some(line_no + added_lines),
+        prev_cursor_x: Some(x),
+        prev_cursor_line: Some(line_no),
+        prev_text: None,
+        selection_start_line: None,
+        changes: vec![Change {
+            x,
+            line_no,
+            old_text: String::new(),
+            new_text: String::from(snippet),
+            kind: ChangeKind::Paste,
+            source_x: None,
+        }],
+        positions,
+        ..options.clone()
+    };
+    parinfer::owned_answer(parinfer::run_mode("smart", &pasted_text, &options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reindent(snippet: &str, x: Column) -> String {
+        let options: Options = serde_json::from_str("{}").unwrap();
+        super::reindent(snippet, x, None, &options)
+    }
+
+    #[test]
+    fn children_keep_their_body_indent() {
+        assert_eq!(reindent("(do\n      (bar))", 2), "(do\n    (bar))");
+        assert_eq!(
+            reindent("(defn foo []\n  (let [x 1]\n    x))", 4),
+            "(defn foo []\n      (let [x 1]\n        x))"
+        );
+    }
+
+    #[test]
+    fn children_keep_their_alignment() {
+        assert_eq!(reindent("[1 2\n    3 4]", 0), "[1 2\n 3 4]");
+        assert_eq!(
+            reindent("(let [x 1\n          y 2]\n      z)", 0),
+            "(let [x 1\n      y 2]\n  z)"
+        );
+    }
+
+    #[test]
+    fn siblings_line_up_with_the_first_line() {
+        assert_eq!(reindent("(a)\n    (b)", 2), "(a)\n  (b)");
+        assert_eq!(reindent("  (a\n    b)", 4), "  (a\n        b)");
+    }
+
+    #[test]
+    fn a_known_source_column_is_used_instead_of_a_guess() {
+        let options: Options = serde_json::from_str("{}").unwrap();
+        let snippet = "(foo a\n       b)";
+        assert_eq!(reindent(snippet, 0), "(foo a\n  b)");
+        assert_eq!(
+            super::reindent(snippet, 0, Some(2), &options),
+            "(foo a\n     b)"
+        );
+        assert_eq!(
+            super::reindent(snippet, 0, Some(0), &options),
+            "(foo a\n       b)"
+        );
+    }
+
+    #[test]
+    fn strings_and_blank_lines_are_left_alone() {
+        assert_eq!(
+            reindent("(foo \"a\n   b\"\n      c)", 0),
+            "(foo \"a\n   b\"\n  c)"
+        );
+        assert_eq!(reindent("(foo\n\n   bar)", 1), "(foo\n\n   bar)");
+    }
+
+    #[test]
+    fn normalize_reindents_pastes_in_the_text() {
+        let mut options: Options = serde_json::from_str("{}").unwrap();
+        options.cursor_line = Some(2);
+        options.cursor_x = Some(9);
+        options.changes = vec![Change {
+            x: 2,
+            line_no: 1,
+            old_text: String::new(),
+            new_text: String::from("(bar\n      baz)"),
+            kind: ChangeKind::Paste,
+            source_x: None,
+        }];
+        let (text, options) = normalize("(foo\n  (bar\n      baz))", &options).unwrap();
+        assert_eq!(text, "(foo\n  (bar\n    baz))");
+        assert_eq!(options.cursor_x, Some(7));
+        assert_eq!(options.changes[0].new_text, "(bar\n    baz)");
+        assert_eq!(options.changes[0].kind, ChangeKind::Edit);
+    }
+
+    #[test]
+    fn paste_inserts_and_reindents_the_snippet() {
+        let options: Options = serde_json::from_str("{}").unwrap();
+        let answer = paste("(defn foo []\n  )", 1, 2, "(let [x 1]\n      x)", &options);
+        assert_eq!(answer.text, "(defn foo []\n  (let [x 1]\n    x))");
+        assert_eq!((answer.cursor_line, answer.cursor_x), (Some(2), Some(6)));
+    }
+
+    #[test]
+    fn pastes_keep_their_structure() {
+        let options: Options = serde_json::from_str("{}").unwrap();
+        let request = |kind, source_x| Request {
+            mode: String::from("smart"),
+            text: String::from("(foo\n  (bar\n      baz))"),
+            options: Options {
+                changes: vec![Change {
+                    x: 2,
+                    line_no: 1,
+                    old_text: String::new(),
+                    new_text: String::from("(bar\n      baz)"),
+                    kind,
+                    source_x,
+                }],
+                ..options.clone()
+            },
+        };
+        assert_eq!(
+            parinfer::process(&request(ChangeKind::Paste, None)).text,
+            "(foo\n  (bar\n    baz))"
+        );
+        assert_eq!(
+            parinfer::process(&request(ChangeKind::Edit, None)).text,
+            "(foo\n  (bar\n      baz))"
+        );
+        // copied from column 2, so `baz` was aligned with `(bar`'s first form
+        assert_eq!(
+            parinfer::process(&request(ChangeKind::Paste, Some(2))).text,
+            "(foo\n  (bar\n      baz))"
+        );
+    }
+}
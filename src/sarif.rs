@@ -0,0 +1,306 @@
+use crate::diff;
+use crate::parinfer::error_message;
+use crate::snippet;
+use crate::types::*;
+use serde_json::{json, Value};
+
+const WOULD_REFORMAT: &str = "would-reformat";
+const SRCROOT: &str = "%SRCROOT%";
+
+const RULES: &[ErrorName] = &[
+    ErrorName::QuoteDanger,
+    ErrorName::EolBackslash,
+    ErrorName::UnclosedQuote,
+    ErrorName::UnclosedParen,
+    ErrorName::UnmatchedCloseParen,
+    ErrorName::UnmatchedOpenParen,
+    ErrorName::LeadingCloseParen,
+    ErrorName::Utf8EncodingError,
+    ErrorName::JsonEncodingError,
+    ErrorName::Panic,
+];
+
+fn rules() -> Vec<Value> {
+    let mut rules = RULES
+        .iter()
+        .map(|&name| {
+            json!({
+                "id": name.to_string(),
+                "shortDescription": { "text": error_message(name) },
+                "defaultConfiguration": { "level": "error" },
+            })
+        })
+        .collect::<Vec<_>>();
+    rules.push(json!({
+        "id": WOULD_REFORMAT,
+        "shortDescription": { "text": "Parinfer would change this line." },
+        "defaultConfiguration": { "level": "warning" },
+    }));
+    rules
+}
+
+// Characters a URI path can hold as they are; `/` separates its segments.
+fn is_uri_safe(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte)
+}
+
+// The file as a URI reference: relative to the `%SRCROOT%` base if its path
+// is relative, or a `file` URI if it's absolute.
+fn artifact_location(filename: &str) -> Value {
+    let path = filename.replace('\\', "/");
+    let path = path.trim_start_matches("./");
+    let encoded: String = path
+        .bytes()
+        .map(|byte| {
+            if is_uri_safe(byte) {
+                char::from(byte).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect();
+    if path.starts_with('/') {
+        json!({ "uri": format!("file://{}", encoded) })
+    } else {
+        json!({ "uri": encoded, "uriBaseId": SRCROOT })
+    }
+}
+
+// Where a result is, or nothing for stdin, which has no URI.
+fn locations(filename: &str, region: Value) -> Option<Value> {
+    if filename == crate::STDIN_FILENAME {
+        return None;
+    }
+    Some(json!([{
+        "physicalLocation": {
+            "artifactLocation": artifact_location(filename),
+            "region": region,
+        }
+    }]))
+}
+
+/// Collects parinfer's findings for any number of files into one SARIF 2.1
+/// log, so they show up alongside other static analysis.
+#[derive(Default)]
+pub struct Report {
+    results: Vec<Value>,
+}
+
+impl Report {
+    pub fn new() -> Report {
+        Report::default()
+    }
+
+    pub fn add_error(&mut self, filename: &str, error: &Error) {
+        let mut result = json!({
+            "ruleId": error.name.to_string(),
+            "level": "error",
+            "message": { "text": error.message },
+        });
+        let region = if snippet::has_position(error.name) {
+            json!({ "startLine": error.line_no + 1, "startColumn": error.x + 1 })
+        } else {
+            json!({ "startLine": 1 })
+        };
+        if let Some(locations) = locations(filename, region) {
+            result["locations"] = locations;
+        }
+        self.results.push(result);
+    }
+
+    /// Adds a "would reformat" finding for each run of lines `mode` changes.
+    pub fn add_changes(&mut self, filename: &str, mode: &str, old: &str, new: &str) {
+        let line_count = old.split_inclusive('\n').count();
+        for lines in diff::changed_lines(old, new) {
+            let message = if lines.len() == 1 {
+                format!("{} mode would change this line", mode)
+            } else if lines.is_empty() {
+                format!("{} mode would add lines here", mode)
+            } else {
+                format!("{} mode would change these lines", mode)
+            };
+            // Added lines are reported on the line they go before, or the
+            // last line when they go at the end.
+            let start = lines.start.min(line_count.saturating_sub(1));
+            let end = lines.end.max(start + 1);
+            let mut result = json!({
+                "ruleId": WOULD_REFORMAT,
+                "level": "warning",
+                "message": { "text": message },
+            });
+            let region = json!({ "startLine": start + 1, "endLine": end });
+            if let Some(locations) = locations(filename, region) {
+                result["locations"] = locations;
+            }
+            self.results.push(result);
+        }
+    }
+
+    /// Adds an answer's errors, and the lines it changed if it succeeded.
+    pub fn add(&mut self, filename: &str, request: &Request, answer: &Answer) {
+        if answer.errors.is_empty() {
+            if let Some(ref error) = answer.error {
+                self.add_error(filename, error);
+            }
+        } else {
+            for error in &answer.errors {
+                self.add_error(filename, error);
+            }
+        }
+        if answer.success {
+            self.add_changes(filename, &request.mode, &request.text, &answer.text);
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let log = json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "parinfer-rust",
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "informationUri": "https://github.com/eraserhd/parinfer-rust",
+                        "rules": rules(),
+                    }
+                },
+                "results": self.results,
+            }]
+        });
+        serde_json::to_string_pretty(&log).expect("unable to produce JSON") + "\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(name: ErrorName, line_no: LineNumber, x: Column) -> Error {
+        Error {
+            name,
+            message: String::from(error_message(name)),
+            line_no,
+            x,
+            input_line_no: line_no,
+            input_x: x,
+            extra: None,
+        }
+    }
+
+    fn log(report: &Report) -> Value {
+        serde_json::from_str(&report.to_json()).unwrap()
+    }
+
+    #[test]
+    fn an_empty_report_is_a_valid_log() {
+        let log = log(&Report::new());
+        assert_eq!(log["version"], "2.1.0");
+        assert_eq!(log["runs"][0]["tool"]["driver"]["name"], "parinfer-rust");
+        assert_eq!(log["runs"][0]["results"], json!([]));
+        let rules = log["runs"][0]["tool"]["driver"]["rules"].as_array().unwrap();
+        assert!(rules.iter().any(|r| r["id"] == "unclosed-paren"));
+        assert!(rules.iter().any(|r| r["id"] == "quote-danger"));
+        assert!(rules.iter().any(|r| r["id"] == WOULD_REFORMAT));
+    }
+
+    #[test]
+    fn errors_become_results() {
+        let mut report = Report::new();
+        report.add_error("a.clj", &error(ErrorName::UnclosedQuote, 2, 4));
+        let result = &log(&report)["runs"][0]["results"][0];
+        assert_eq!(result["ruleId"], "unclosed-quote");
+        assert_eq!(result["level"], "error");
+        assert_eq!(result["message"]["text"], "String is missing a closing quote.");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "a.clj");
+        assert_eq!(location["region"], json!({"startLine": 3, "startColumn": 5}));
+    }
+
+    fn regions(report: &Report) -> Vec<Value> {
+        log(report)["runs"][0]["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                assert_eq!(r["ruleId"], WOULD_REFORMAT);
+                r["locations"][0]["physicalLocation"]["region"].clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn each_changed_run_of_lines_is_a_result() {
+        let mut report = Report::new();
+        report.add_changes("a.clj", "paren", "(a\nb\nc\nd)", "(a\n b\nc\n d)");
+        assert_eq!(
+            regions(&report),
+            vec![
+                json!({"startLine": 2, "endLine": 2}),
+                json!({"startLine": 4, "endLine": 4}),
+            ]
+        );
+
+        // collapsing blank lines doesn't shift the lines after them
+        let mut report = Report::new();
+        report.add_changes(
+            "a.clj",
+            "indent",
+            "(a)\n\n\n\n(b)\n(c\n",
+            "(a)\n\n(b)\n(c)\n",
+        );
+        assert_eq!(
+            regions(&report),
+            vec![
+                json!({"startLine": 3, "endLine": 4}),
+                json!({"startLine": 6, "endLine": 6}),
+            ]
+        );
+
+        // a final newline is added to the last line
+        let mut report = Report::new();
+        report.add_changes("a.clj", "indent", "(a)\n(b)", "(a)\n(b)\n");
+        assert_eq!(
+            regions(&report),
+            vec![json!({"startLine": 2, "endLine": 2})]
+        );
+    }
+
+    #[test]
+    fn successful_answers_report_their_errors_too() {
+        let request = Request {
+            mode: String::from("smart"),
+            text: String::from("(a\n b"),
+            options: serde_json::from_str("{}").unwrap(),
+        };
+        let mut answer = crate::parinfer::process(&request);
+        answer.errors = vec![error(ErrorName::UnclosedQuote, 0, 1)];
+        let mut report = Report::new();
+        report.add("a.clj", &request, &answer);
+        let results = log(&report)["runs"][0]["results"].clone();
+        assert_eq!(results[0]["ruleId"], "unclosed-quote");
+        assert_eq!(results[1]["ruleId"], WOULD_REFORMAT);
+    }
+
+    #[test]
+    fn locations_are_uri_references() {
+        let uri = |filename: &str| artifact_location(filename);
+        assert_eq!(
+            uri("src/a.clj"),
+            json!({"uri": "src/a.clj", "uriBaseId": SRCROOT})
+        );
+        assert_eq!(
+            uri("./my dir/ä#1.clj"),
+            json!({"uri": "my%20dir/%C3%A4%231.clj", "uriBaseId": SRCROOT})
+        );
+        assert_eq!(uri("/tmp/a.clj"), json!({"uri": "file:///tmp/a.clj"}));
+
+        let mut report = Report::new();
+        report.add_error(
+            crate::STDIN_FILENAME,
+            &error(ErrorName::UnclosedQuote, 0, 1),
+        );
+        let result = &log(&report)["runs"][0]["results"][0];
+        assert!(result.get("locations").is_none());
+    }
+}
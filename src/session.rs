@@ -0,0 +1,183 @@
+//! Session logs: one JSON line per request, with the answer it got, so a
+//! sequence of edits can be replayed exactly.
+//!
+//! Logging is enabled for any frontend by setting `PARINFER_RUST_SESSION_LOG`
+//! to the file to append to.
+
+use crate::parinfer;
+use crate::types::*;
+use serde_json::Value;
+use std::env;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+pub const LOG_ENV: &str = "PARINFER_RUST_SESSION_LOG";
+
+#[derive(Deserialize)]
+struct LoggedEntry {
+    request: Request,
+    answer: Value,
+}
+
+/// Appends a request and its answer to the session log, if there is one.
+/// Failing to log never gets in the way of editing, so errors are ignored.
+pub fn record(request: &Request, answer: &Answer) {
+    match env::var_os(LOG_ENV) {
+        Some(path) if !path.is_empty() => record_to(Path::new(&path), request, answer),
+        _ => (),
+    }
+}
+
+fn record_to(path: &Path, request: &Request, answer: &Answer) {
+    let line = serde_json::json!({ "request": request, "answer": answer }).to_string() + "\n";
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+        let _ = file.write_all(line.as_bytes());
+    }
+}
+
+/// The first logged answer that came out differently on replay.
+pub struct Mismatch {
+    /// The line of the log, counting from 1.
+    pub line_no: usize,
+    pub request: Request,
+    pub expected: Value,
+    pub actual: Value,
+}
+
+impl Mismatch {
+    /// The answer fields that differ, as `(name, expected, actual)`.
+    pub fn differences(&self) -> Vec<(String, Value, Value)> {
+        let empty = serde_json::Map::new();
+        let expected = self.expected.as_object().unwrap_or(&empty);
+        let actual = self.actual.as_object().unwrap_or(&empty);
+        let mut keys = expected.keys().chain(actual.keys()).cloned().collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        keys.into_iter()
+            .filter_map(|key| {
+                let e = expected.get(&key).cloned().unwrap_or(Value::Null);
+                let a = actual.get(&key).cloned().unwrap_or(Value::Null);
+                if e == a {
+                    None
+                } else {
+                    Some((key, e, a))
+                }
+            })
+            .collect()
+    }
+}
+
+pub struct Replay {
+    /// How many requests were run.
+    pub count: usize,
+    pub mismatch: Option<Mismatch>,
+}
+
+/// Re-runs every request in a session log, stopping at the first answer that
+/// differs from the logged one.
+pub fn replay(log: impl BufRead) -> io::Result<Replay> {
+    let mut count = 0;
+    for (i, line) in log.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: LoggedEntry = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", i + 1, e))
+        })?;
+        count += 1;
+        let actual = serde_json::to_value(parinfer::process(&entry.request))?;
+        if actual != entry.answer {
+            return Ok(Replay {
+                count,
+                mismatch: Some(Mismatch {
+                    line_no: i + 1,
+                    request: entry.request,
+                    expected: entry.answer,
+                    actual,
+                }),
+            });
+        }
+    }
+    Ok(Replay {
+        count,
+        mismatch: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(mode: &str, text: &str) -> Request {
+        Request {
+            mode: String::from(mode),
+            text: String::from(text),
+            options: serde_json::from_str("{}").unwrap(),
+        }
+    }
+
+    fn entry(request: &Request) -> Value {
+        serde_json::json!({ "request": request, "answer": parinfer::process(request) })
+    }
+
+    #[test]
+    fn record_appends_json_lines() {
+        let path = env::temp_dir().join(format!("parinfer-session-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let first = request("indent", "(foo");
+        let second = request("paren", "(foo\nbar)");
+        record_to(&path, &first, &parinfer::process(&first));
+        record_to(&path, &second, &parinfer::process(&second));
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let logged: Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(logged, entry(&second));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replay_of_a_faithful_log_matches() {
+        let log = format!(
+            "{}\n{}\n",
+            entry(&request("indent", "(foo")),
+            entry(&request("smart", "(foo\n  bar"))
+        );
+        let replay = replay(log.as_bytes()).unwrap();
+        assert_eq!(replay.count, 2);
+        assert!(replay.mismatch.is_none());
+    }
+
+    #[test]
+    fn replay_stops_at_the_first_difference() {
+        let mut changed = entry(&request("paren", "(foo\nbar)"));
+        changed["answer"]["text"] = Value::from("(foo\nbar)");
+        let log = format!(
+            "{}\n{}\n{}\n",
+            entry(&request("indent", "(foo")),
+            changed,
+            entry(&request("indent", "(bar"))
+        );
+        let replay = replay(log.as_bytes()).unwrap();
+        assert_eq!(replay.count, 2);
+        let mismatch = replay.mismatch.unwrap();
+        assert_eq!(mismatch.line_no, 2);
+        assert_eq!(
+            mismatch.differences(),
+            vec![(
+                String::from("text"),
+                Value::from("(foo\nbar)"),
+                Value::from("(foo\n bar)")
+            )]
+        );
+    }
+
+    #[test]
+    fn bad_lines_are_errors() {
+        assert!(replay("{\"request\": 1}\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,318 @@
+use crate::parinfer::chomp_cr;
+use crate::types::*;
+
+struct Style {
+    error: &'static str,
+    primary: &'static str,
+    secondary: &'static str,
+    gutter: &'static str,
+    bold: &'static str,
+    reset: &'static str,
+}
+
+const COLOR: Style = Style {
+    error: "\x1b[1;31m",
+    primary: "\x1b[1;31m",
+    secondary: "\x1b[1;34m",
+    gutter: "\x1b[1;34m",
+    bold: "\x1b[1m",
+    reset: "\x1b[0m",
+};
+
+const PLAIN: Style = Style {
+    error: "",
+    primary: "",
+    secondary: "",
+    gutter: "",
+    bold: "",
+    reset: "",
+};
+
+struct Marker {
+    line_no: LineNumber,
+    x: Column,
+    primary: bool,
+    label: Option<&'static str>,
+}
+
+/// Whether errors with this name point at a place in the text.
+pub fn has_position(name: ErrorName) -> bool {
+    matches!(
+        name,
+        ErrorName::QuoteDanger
+            | ErrorName::EolBackslash
+            | ErrorName::UnclosedQuote
+            | ErrorName::UnclosedParen
+            | ErrorName::UnmatchedCloseParen
+            | ErrorName::UnmatchedOpenParen
+            | ErrorName::LeadingCloseParen
+    )
+}
+
+fn extra_label(name: ErrorName) -> &'static str {
+    match name {
+        ErrorName::UnclosedParen => "close-paren expected here",
+        _ => "open-paren",
+    }
+}
+
+fn markers(error: &Error) -> Vec<Marker> {
+    let mut markers = vec![Marker {
+        line_no: error.line_no,
+        x: error.x,
+        primary: true,
+        label: None,
+    }];
+    if let Some(ref extra) = error.extra {
+        markers.push(Marker {
+            line_no: extra.line_no,
+            x: extra.x,
+            primary: false,
+            label: Some(extra_label(extra.name)),
+        });
+    }
+    markers.sort_by_key(|m| (m.line_no, m.x));
+    markers
+}
+
+fn marker_line(style: &Style, gutter: &str, markers: &[&Marker]) -> String {
+    let mut line = format!("{}{} |{} ", style.gutter, gutter, style.reset);
+    let mut column = 0;
+    for marker in markers {
+        line.push_str(&" ".repeat(marker.x.saturating_sub(column)));
+        if marker.primary {
+            line.push_str(&format!("{}^{}", style.primary, style.reset));
+        } else {
+            line.push_str(&format!("{}-{}", style.secondary, style.reset));
+        }
+        column = marker.x + 1;
+    }
+    // The last marker's label fits on the same line, others go underneath.
+    if let Some(label) = markers.last().and_then(|m| m.label) {
+        line.push_str(&format!(" {}{}{}", style.secondary, label, style.reset));
+    }
+    line.push('\n');
+    for marker in markers[..markers.len() - 1].iter().rev() {
+        if let Some(label) = marker.label {
+            line.push_str(&format!(
+                "{}{} |{} {}{}{}{}\n",
+                style.gutter,
+                gutter,
+                style.reset,
+                " ".repeat(marker.x),
+                style.secondary,
+                label,
+                style.reset
+            ));
+        }
+    }
+    line
+}
+
+/// Renders an error like rustc does: a header, the location, and the
+/// offending source line with a caret under the column.  The related paren
+/// from `error.extra`, if any, is marked too.
+pub fn render_error(filename: &str, text: &str, error: &Error, color: bool) -> String {
+    let style = if color { &COLOR } else { &PLAIN };
+    let mut out = format!(
+        "{}error[{}]{}{}: {}{}\n",
+        style.error, error.name, style.reset, style.bold, error.message, style.reset
+    );
+    if !has_position(error.name) {
+        return out;
+    }
+
+    let lines: Vec<&str> = text.split('\n').map(chomp_cr).collect();
+    let markers = markers(error);
+    let width = markers
+        .iter()
+        .map(|m| (m.line_no + 1).to_string().len())
+        .max()
+        .unwrap_or(1);
+    let blank = " ".repeat(width);
+
+    out.push_str(&format!(
+        "{}{}-->{} {}:{}:{}\n",
+        style.gutter,
+        " ".repeat(width),
+        style.reset,
+        filename,
+        error.line_no + 1,
+        error.x + 1
+    ));
+    out.push_str(&format!("{}{} |{}\n", style.gutter, blank, style.reset));
+
+    let mut previous_line_no: Option<LineNumber> = None;
+    for marker in &markers {
+        if previous_line_no == Some(marker.line_no) {
+            continue;
+        }
+        if previous_line_no.map(|n| n + 1 < marker.line_no).unwrap_or(false) {
+            out.push_str(&format!("{}...{}\n", style.gutter, style.reset));
+        }
+        let line_markers: Vec<&Marker> = markers
+            .iter()
+            .filter(|m| m.line_no == marker.line_no)
+            .collect();
+        out.push_str(&format!(
+            "{}{:>width$} |{} {}\n",
+            style.gutter,
+            marker.line_no + 1,
+            style.reset,
+            lines.get(marker.line_no).unwrap_or(&""),
+            width = width
+        ));
+        out.push_str(&marker_line(style, &blank, &line_markers));
+        previous_line_no = Some(marker.line_no);
+    }
+    out
+}
+
+/// Renders an error on one line as `file:line:col: error: message [name]`,
+/// which editors' quickfix lists and CI annotators understand.
+pub fn render_diagnostic(filename: &str, error: &Error) -> String {
+    if has_position(error.name) {
+        format!(
+            "{}:{}:{}: error: {} [{}]\n",
+            filename,
+            error.line_no + 1,
+            error.x + 1,
+            error.message,
+            error.name
+        )
+    } else {
+        format!("{}: error: {} [{}]\n", filename, error.message, error.name)
+    }
+}
+
+/// Renders a decision from an explained answer like a diagnostic, as
+/// `file:line:col: note: message [name]`.
+pub fn render_decision(filename: &str, decision: &Decision) -> String {
+    format!(
+        "{}:{}:{}: note: {} [{}]\n",
+        filename,
+        decision.line_no + 1,
+        decision.x + 1,
+        decision.message,
+        decision.name
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn error(name: ErrorName, line_no: LineNumber, x: Column) -> Error {
+        Error {
+            name,
+            message: String::from("Message."),
+            line_no,
+            x,
+            input_line_no: line_no,
+            input_x: x,
+            extra: None,
+        }
+    }
+
+    #[test]
+    fn renders_a_caret_under_the_column() {
+        assert_eq!(
+            render_error(
+                "foo.clj",
+                "(foo\n  \"bar)",
+                &error(ErrorName::UnclosedQuote, 1, 2),
+                false
+            ),
+            "error[unclosed-quote]: Message.\n \
+             --> foo.clj:2:3\n  \
+             |\n\
+             2 |   \"bar)\n  \
+             |   ^\n"
+        );
+    }
+
+    #[test]
+    fn marks_the_related_opener_on_the_same_line() {
+        let mut e = error(ErrorName::UnmatchedCloseParen, 0, 9);
+        e.extra = Some(ErrorExtra {
+            name: ErrorName::UnmatchedOpenParen,
+            line_no: 0,
+            x: 5,
+            input_line_no: 0,
+            input_x: 5,
+        });
+        assert_eq!(
+            render_error("<stdin>", "(foo [bar) baz", &e, false),
+            "error[unmatched-close-paren]: Message.\n \
+             --> <stdin>:1:10\n  \
+             |\n\
+             1 | (foo [bar) baz\n  \
+             |      -   ^\n  \
+             |      open-paren\n"
+        );
+    }
+
+    #[test]
+    fn marks_the_related_position_on_another_line() {
+        let mut e = error(ErrorName::UnclosedParen, 0, 0);
+        e.extra = Some(ErrorExtra {
+            name: ErrorName::UnclosedParen,
+            line_no: 11,
+            x: 3,
+            input_line_no: 11,
+            input_x: 3,
+        });
+        let text = "(foo\n".to_string() + &"bar\n".repeat(11);
+        assert_eq!(
+            render_error("<stdin>", &text, &e, false),
+            "error[unclosed-paren]: Message.\n  \
+             --> <stdin>:1:1\n   \
+             |\n \
+             1 | (foo\n   \
+             | ^\n\
+             ...\n\
+             12 | bar\n   \
+             |    - close-paren expected here\n"
+        );
+    }
+
+    #[test]
+    fn errors_without_a_position_only_have_a_header() {
+        assert_eq!(
+            render_error("<stdin>", "", &error(ErrorName::JsonEncodingError, 0, 0), false),
+            "error[json-error]: Message.\n"
+        );
+    }
+
+    #[test]
+    fn renders_diagnostics_with_one_based_positions() {
+        assert_eq!(
+            render_diagnostic("foo.clj", &error(ErrorName::UnclosedQuote, 1, 2)),
+            "foo.clj:2:3: error: Message. [unclosed-quote]\n"
+        );
+        assert_eq!(
+            render_diagnostic("foo.clj", &error(ErrorName::Utf8EncodingError, 0, 0)),
+            "foo.clj: error: Message. [utf8-error]\n"
+        );
+    }
+
+    #[test]
+    fn renders_decisions_as_notes() {
+        let decision = Decision {
+            name: DecisionName::CloseParenMoved,
+            message: String::from("Message."),
+            x: 2,
+            line_no: 1,
+        };
+        assert_eq!(
+            render_decision("foo.clj", &decision),
+            "foo.clj:2:3: note: Message. [close-paren-moved]\n"
+        );
+    }
+
+    #[test]
+    fn uses_color_when_asked() {
+        let out = render_error("<stdin>", "(", &error(ErrorName::UnclosedParen, 0, 0), true);
+        assert!(out.starts_with("\x1b[1;31merror[unclosed-paren]\x1b[0m"));
+    }
+}
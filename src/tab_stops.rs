@@ -0,0 +1,231 @@
+//! Tab and Shift-Tab: snapping lines to the next or previous tab stop.
+
+use crate::parinfer;
+use crate::types::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+const DIRECTIONS: [(&str, Direction); 2] = [
+    ("indent-forward", Direction::Forward),
+    ("indent-backward", Direction::Backward),
+];
+
+impl Direction {
+    /// The mode name that requests a shift in this direction.
+    pub fn name(self) -> &'static str {
+        DIRECTIONS
+            .iter()
+            .find(|(_, direction)| *direction == self)
+            .map(|(name, _)| *name)
+            .unwrap()
+    }
+}
+
+impl std::str::FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Direction, String> {
+        DIRECTIONS
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, direction)| *direction)
+            .ok_or_else(|| format!("unknown direction: {}", s))
+    }
+}
+
+/// The columns a line can snap to: each opener's own column, just inside
+/// it, and its first argument.
+fn stops(tab_stops: &[TabStop]) -> Vec<Column> {
+    let mut stops: Vec<Column> = tab_stops
+        .iter()
+        .flat_map(|tab_stop| [Some(tab_stop.x), Some(tab_stop.x + 1), tab_stop.arg_x])
+        .flatten()
+        .collect();
+    stops.sort_unstable();
+    stops.dedup();
+    stops
+}
+
+/// Shifts the cursor line, or every line from `selection_start_line` to the
+/// cursor line, so that the first of them moves to the next or previous tab
+/// stop.  Returns the new text and the options to run smart mode over it
+/// with: the changes made, which let it move children along, and the cursor
+/// and tracked positions moved with their lines.  Returns `None` if there is
+/// no cursor or no stop in that direction.
+pub fn shift(direction: Direction, text: &str, options: &Options) -> Option<(String, Options)> {
+    let (cursor_line, cursor_x) = (options.cursor_line?, options.cursor_x?);
+    let first = options
+        .selection_start_line
+        .unwrap_or(cursor_line)
+        .min(cursor_line);
+    let last = options
+        .selection_start_line
+        .unwrap_or(cursor_line)
+        .max(cursor_line);
+    let lines: Vec<&str> = text.split('\n').collect();
+    if last >= lines.len() {
+        return None;
+    }
+
+    let tab_stop_options = Options {
+        cursor_x: Some(0),
+        cursor_line: Some(first),
+        prev_cursor_x: None,
+        prev_cursor_line: None,
+        prev_text: None,
+        selection_start_line: None,
+        changes: vec![],
+        ..options.clone()
+    };
+    let answer = parinfer::paren_mode(text, &tab_stop_options);
+    let stops = stops(&answer.tab_stops);
+    let indent = parinfer::indentation(lines[first]);
+    let target = match direction {
+        Direction::Forward => stops.into_iter().find(|&x| x > indent)?,
+        Direction::Backward => stops.into_iter().rev().find(|&x| x < indent)?,
+    };
+
+    let mut changes = vec![];
+    let mut new_cursor_x = cursor_x;
+    let mut positions = options.positions.clone();
+    let mut output: Vec<String> = vec![];
+    for (line_no, line) in lines.into_iter().enumerate() {
+        if line_no < first || line_no > last || line.trim_end_matches('\r').is_empty() {
+            output.push(String::from(line));
+            continue;
+        }
+        let old_indent = parinfer::indentation(line);
+        let new_indent = (old_indent + target).saturating_sub(indent);
+        let (old_text, new_text) = if new_indent > old_indent {
+            (String::new(), " ".repeat(new_indent - old_indent))
+        } else {
+            (" ".repeat(old_indent - new_indent), String::new())
+        };
+        if line_no == cursor_line {
+            new_cursor_x = (cursor_x + new_indent).saturating_sub(old_indent);
+        }
+        for position in positions.iter_mut().filter(|p| p.line_no == line_no) {
+            position.x = (position.x + new_indent).saturating_sub(old_indent);
+        }
+        output.push(format!("{}{}", new_text, &line[old_text.len()..]));
+        changes.push(Change {
+            x: 0,
+            line_no,
+            old_text,
+            new_text,
+            kind: ChangeKind::Edit,
+            source_x: None,
+        });
+    }
+    let options = Options {
+        cursor_x: Some(new_cursor_x),
+        prev_cursor_x: None,
+        prev_cursor_line: None,
+        prev_text: None,
+        selection_start_line: None,
+        changes,
+        positions,
+        ..options.clone()
+    };
+    Some((output.join("\n"), options))
+}
+
+/// Snaps the cursor line or the selected lines to the next or previous tab
+/// stop, then runs smart mode so their children move with them.
+pub(crate) fn tab_mode<'a>(direction: Direction, text: &'a str, options: &Options) -> Answer<'a> {
+    match shift(direction, text, options) {
+        Some((shifted, options)) => {
+            parinfer::owned_answer(parinfer::smart_mode(&shifted, &options))
+        }
+        None => parinfer::smart_mode(text, options),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotated;
+
+    fn run(mode: &str, input: &str, selection_start_line: Option<LineNumber>) -> String {
+        let input = annotated::parse(input).unwrap();
+        let mut options: Options = serde_json::from_str("{}").unwrap();
+        options.cursor_x = input.cursor_x;
+        options.cursor_line = input.cursor_line;
+        options.selection_start_line = selection_start_line;
+        let request = Request {
+            mode: String::from(mode),
+            text: input.text,
+            options,
+        };
+        let answer = parinfer::process(&request);
+        annotated::print(&annotated::Annotated::from_answer(&answer))
+    }
+
+    #[test]
+    fn direction_names_round_trip() {
+        for (name, direction) in DIRECTIONS.iter() {
+            assert_eq!(name.parse::<Direction>(), Ok(*direction));
+            assert_eq!(direction.name(), *name);
+        }
+    }
+
+    #[test]
+    fn stops_include_openers_their_insides_and_arguments() {
+        let tab_stop = |x, arg_x| TabStop {
+            ch: "(",
+            x,
+            line_no: 0,
+            arg_x,
+        };
+        assert_eq!(
+            stops(&[tab_stop(0, Some(6)), tab_stop(5, None)]),
+            vec![0, 1, 5, 6]
+        );
+    }
+
+    #[test]
+    fn indents_to_the_next_stop_and_children_follow() {
+        assert_eq!(
+            run("indent-forward", "(defn foo [a b]\n  |(+ a\n     b))", None),
+            "(defn foo [a b]\n      |(+ a\n         b))"
+        );
+        assert_eq!(
+            run("indent-forward", "(let [x 1]\n  |y)\nz", None),
+            "(let [x 1]\n     |y)\nz"
+        );
+    }
+
+    #[test]
+    fn dedents_to_the_previous_stop() {
+        assert_eq!(
+            run(
+                "indent-backward",
+                "(defn foo [a b]\n      |(+ a\n         b))",
+                None
+            ),
+            "(defn foo [a b]\n |(+ a\n    b))"
+        );
+        assert_eq!(
+            run("indent-backward", "(defn foo [a b]\n |(+ a\n     b))", None),
+            "(defn foo [a b])\n|(+ a\n    b)"
+        );
+    }
+
+    #[test]
+    fn shifts_selected_lines_together() {
+        assert_eq!(
+            run("indent-forward", "(let [x 1]\n  a\n  |b)", Some(1)),
+            "(let [x 1]\n     a\n     |b)"
+        );
+    }
+
+    #[test]
+    fn does_nothing_without_a_stop() {
+        assert_eq!(run("indent-backward", "|(foo)", None), "|(foo)");
+        assert_eq!(run("indent-forward", "(foo\n |bar)", None), "(foo\n |bar)");
+    }
+}
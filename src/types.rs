@@ -1,19 +1,79 @@
-use std::{fmt, mem, rc::Rc};
+use std::{collections::BTreeMap, fmt, mem, rc::Rc};
 
 pub type LineNumber = usize;
 pub type Column = usize;
 pub type Delta = i64;
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct Change {
     pub x: Column,
     pub line_no: LineNumber,
     pub old_text: String,
     pub new_text: String,
+    #[serde(default, rename = "type", skip_serializing_if = "ChangeKind::is_edit")]
+    pub kind: ChangeKind,
+    /// For a paste, the column its text was copied from, if the editor
+    /// knows.  Otherwise it is guessed from the text's indentation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_x: Option<Column>,
 }
 
-#[derive(Clone, Deserialize, Debug)]
+/// What made a change.  Pasted text is reindented to the column it was
+/// pasted at before parinfer runs.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    #[default]
+    Edit,
+    Paste,
+}
+
+impl ChangeKind {
+    fn is_edit(&self) -> bool {
+        *self == ChangeKind::Edit
+    }
+}
+
+/// How `format` mode indents the lines of a form whose head has a rule.
+/// `Inner` gives every line a two-space body indent; `Block(n)` does so when
+/// the form breaks its line before the form after its first `n` arguments.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Indent {
+    Inner,
+    Block(usize),
+}
+
+/// How `format` mode indents the other lists: aligned with their first
+/// argument when it is on the opening line, or always one space in.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ListStyle {
+    #[default]
+    Align,
+    OneSpace,
+}
+
+/// A prose format whose code blocks parinfer processes, leaving the rest of
+/// the document alone.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Document {
+    Markdown,
+    Org,
+}
+
+/// A position an editor wants kept in step with parinfer's edits, like a
+/// mark, a breakpoint or another selection.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Position {
+    pub x: Column,
+    pub line_no: LineNumber,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Options {
     pub cursor_x: Option<Column>,
@@ -40,6 +100,30 @@ pub struct Options {
     pub janet_long_strings: bool,
     #[serde(default = "Options::default_false")]
     pub hy_bracket_strings: bool,
+    #[serde(default = "Options::default_false")]
+    pub isolate_forms: bool,
+    #[serde(default = "Options::default_false")]
+    pub report_all_errors: bool,
+    #[serde(default = "Options::default_positions")]
+    pub positions: Vec<Position>,
+    #[serde(default = "Options::default_indents")]
+    pub indents: BTreeMap<String, Indent>,
+    #[serde(default)]
+    pub list_style: ListStyle,
+    #[serde(default = "Options::default_false")]
+    pub trim_trailing_whitespace: bool,
+    #[serde(default)]
+    pub tab_width: Option<Column>,
+    #[serde(default = "Options::default_false")]
+    pub preserve_tabs: bool,
+    #[serde(default)]
+    pub max_blank_lines: Option<usize>,
+    #[serde(default = "Options::default_false")]
+    pub final_newline: bool,
+    #[serde(default = "Options::default_false")]
+    pub explain: bool,
+    #[serde(default)]
+    pub document: Option<Document>,
 }
 
 impl Options {
@@ -47,6 +131,14 @@ impl Options {
         vec![]
     }
 
+    fn default_positions() -> Vec<Position> {
+        vec![]
+    }
+
+    fn default_indents() -> BTreeMap<String, Indent> {
+        crate::format::indents_for("clojure")
+    }
+
     fn default_false() -> bool {
         false
     }
@@ -59,7 +151,7 @@ impl Options {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Request {
     pub mode: String,
@@ -86,6 +178,9 @@ pub struct ParenTrail {
 
 #[derive(Clone, Debug)]
 pub struct Closer {
+    pub ch: &'static str,
+    pub input_line_no: LineNumber,
+    pub input_x: Column,
     pub trail: Option<ParenTrail>,
 }
 
@@ -107,6 +202,84 @@ pub struct Paren<'a> {
     pub children: Vec<Paren<'a>>,
 }
 
+/// A top-level form, for outline views.  `head` is its first symbol and
+/// `name` is the head with the form after it, like `defn foo`.  The end is
+/// just after its closer.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct OutlineForm {
+    pub line_no: LineNumber,
+    pub x: Column,
+    pub end_line_no: LineNumber,
+    pub end_x: Column,
+    pub head: Option<String>,
+    pub name: Option<String>,
+}
+
+/// A list spanning several lines, which an editor can fold, from its opener
+/// to just after its closer.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Fold {
+    pub line_no: LineNumber,
+    pub x: Column,
+    pub end_line_no: LineNumber,
+    pub end_x: Column,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Outline {
+    pub forms: Vec<OutlineForm>,
+    pub folds: Vec<Fold>,
+}
+
+/// The kinds of change parinfer explains when asked to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecisionName {
+    ParenTrailMoved,
+    ParenTrailHeld,
+    CloseParenRemoved,
+    CloseParenMoved,
+    CloseParenKept,
+    IndentShifted,
+    IndentClamped,
+}
+
+impl fmt::Display for DecisionName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecisionName::ParenTrailMoved => f.write_str("paren-trail-moved"),
+            DecisionName::ParenTrailHeld => f.write_str("paren-trail-held"),
+            DecisionName::CloseParenRemoved => f.write_str("close-paren-removed"),
+            DecisionName::CloseParenMoved => f.write_str("close-paren-moved"),
+            DecisionName::CloseParenKept => f.write_str("close-paren-kept"),
+            DecisionName::IndentShifted => f.write_str("indent-shifted"),
+            DecisionName::IndentClamped => f.write_str("indent-clamped"),
+        }
+    }
+}
+
+impl serde::Serialize for DecisionName {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// Why parinfer changed the text where it did, reported in `trace` when the
+/// request sets `explain`.  The position is in the output text.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Decision {
+    pub name: DecisionName,
+    pub message: String,
+    pub x: Column,
+    pub line_no: LineNumber,
+}
+
 #[derive(Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Answer<'a> {
@@ -118,6 +291,19 @@ pub struct Answer<'a> {
     pub tab_stops: Vec<TabStop<'a>>,
     pub paren_trails: Vec<ParenTrail>,
     pub parens: Vec<Paren<'a>>,
+    /// Every error, with `report_all_errors`.  With `isolate_forms` or a
+    /// `document`, the errors of the forms or code blocks which were left
+    /// untouched: `success` is false and `error` is the first of them, but
+    /// `text` has everything else processed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<Error>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outline: Option<Outline>,
+    /// The request's `positions`, moved along with the text around them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub positions: Vec<Position>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub trace: Vec<Decision>,
 }
 
 impl<'a> From<Error> for Answer<'a> {
@@ -131,6 +317,10 @@ impl<'a> From<Error> for Answer<'a> {
             tab_stops: vec![],
             paren_trails: vec![],
             parens: vec![],
+            errors: vec![],
+            outline: None,
+            positions: vec![],
+            trace: vec![],
         }
     }
 }
@@ -171,6 +361,26 @@ impl fmt::Display for ErrorName {
     }
 }
 
+impl std::str::FromStr for ErrorName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ErrorName, String> {
+        match s {
+            "quote-danger" => Ok(ErrorName::QuoteDanger),
+            "eol-backslash" => Ok(ErrorName::EolBackslash),
+            "unclosed-quote" => Ok(ErrorName::UnclosedQuote),
+            "unclosed-paren" => Ok(ErrorName::UnclosedParen),
+            "unmatched-close-paren" => Ok(ErrorName::UnmatchedCloseParen),
+            "unmatched-open-paren" => Ok(ErrorName::UnmatchedOpenParen),
+            "leading-close-paren" => Ok(ErrorName::LeadingCloseParen),
+            "utf8-error" => Ok(ErrorName::Utf8EncodingError),
+            "json-error" => Ok(ErrorName::JsonEncodingError),
+            "panic" => Ok(ErrorName::Panic),
+            _ => Err(format!("unknown error name: {}", s)),
+        }
+    }
+}
+
 impl serde::Serialize for ErrorName {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -198,19 +408,7 @@ impl<'a> serde::Deserialize<'a> for ErrorName {
             where
                 E: serde::de::Error,
             {
-                match value {
-                    "quote-danger" => Ok(ErrorName::QuoteDanger),
-                    "eol-backslash" => Ok(ErrorName::EolBackslash),
-                    "unclosed-quote" => Ok(ErrorName::UnclosedQuote),
-                    "unclosed-paren" => Ok(ErrorName::UnclosedParen),
-                    "unmatched-close-paren" => Ok(ErrorName::UnmatchedCloseParen),
-                    "unmatched-open-paren" => Ok(ErrorName::UnmatchedOpenParen),
-                    "leading-close-paren" => Ok(ErrorName::LeadingCloseParen),
-                    "utf8-error" => Ok(ErrorName::Utf8EncodingError),
-                    "json-error" => Ok(ErrorName::JsonEncodingError),
-                    "panic" => Ok(ErrorName::Panic),
-                    _ => Err(E::custom(format!("unknown error name: {}", value))),
-                }
+                value.parse().map_err(E::custom)
             }
         }
 
@@ -218,6 +416,19 @@ impl<'a> serde::Deserialize<'a> for ErrorName {
     }
 }
 
+/// The position of the other paren involved in an error, like parinfer.js's
+/// `error.extra`.  For `unmatched-close-paren`, this is the opener it should
+/// have matched; for `unclosed-paren`, this is where its closer is missing.
+#[derive(Debug, Default, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorExtra {
+    pub name: ErrorName,
+    pub x: Column,
+    pub line_no: LineNumber,
+    pub input_x: Column,
+    pub input_line_no: LineNumber,
+}
+
 #[derive(Debug, Default, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Error {
@@ -227,6 +438,8 @@ pub struct Error {
     pub line_no: LineNumber,
     pub input_x: Column,
     pub input_line_no: LineNumber,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra: Option<ErrorExtra>,
 }
 
 impl From<std::str::Utf8Error> for Error {
@@ -259,6 +472,17 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+#[cfg(target_arch = "wasm32")]
+impl From<serde_wasm_bindgen::Error> for Error {
+    fn from(error: serde_wasm_bindgen::Error) -> Error {
+        Error {
+            name: ErrorName::JsonEncodingError,
+            message: format!("Error converting JavaScript value: {}", error),
+            ..Error::default()
+        }
+    }
+}
+
 // Introduce the concept of Reference Counting of requests to work with emacs memory module
 #[allow(dead_code)]
 pub type SharedRequest = Rc<Request>;
@@ -274,6 +498,9 @@ pub struct WrappedAnswer {
 }
 
 impl WrappedAnswer {
+    /// # Safety
+    ///
+    /// `inner` must borrow from `request`, which the wrapper keeps alive.
     #[inline]
     #[allow(dead_code)]
     pub unsafe fn new(request: SharedRequest, inner: Answer) -> Self {
@@ -286,7 +513,7 @@ impl WrappedAnswer {
 
     #[inline]
     #[allow(dead_code)]
-    pub fn inner(&self) -> &Answer {
+    pub fn inner(&self) -> &Answer<'_> {
         let ptr = (&self.raw as *const RawAnswer) as *const Answer;
         unsafe { &*ptr }
     }
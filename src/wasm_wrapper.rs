@@ -1,21 +1,214 @@
+use super::*;
 use super::common_wrapper;
-use serde_json;
-use std::panic;
+use serde::Serialize;
+use serde_wasm_bindgen::Serializer;
 use types::*;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
 
+#[wasm_bindgen(typescript_custom_section)]
+const TYPESCRIPT_DEFINITIONS: &'static str = r#"
+export interface ParinferChange {
+  x: number;
+  lineNo: number;
+  oldText: string;
+  newText: string;
+  type?: 'paste';
+  sourceX?: number | null;
+}
+
+export interface ParinferPosition {
+  x: number;
+  lineNo: number;
+}
+
+export interface ParinferOptions {
+  cursorX?: number | null;
+  cursorLine?: number | null;
+  prevCursorX?: number | null;
+  prevCursorLine?: number | null;
+  prevText?: string | null;
+  selectionStartLine?: number | null;
+  changes?: ParinferChange[];
+  commentChar?: string;
+  stringDelimiters?: string[];
+  lispVlineSymbols?: boolean;
+  lispBlockComments?: boolean;
+  guileBlockComments?: boolean;
+  schemeSexpComments?: boolean;
+  janetLongStrings?: boolean;
+  hyBracketStrings?: boolean;
+  isolateForms?: boolean;
+  reportAllErrors?: boolean;
+  positions?: ParinferPosition[];
+  indents?: { [head: string]: "inner" | { block: number } };
+  listStyle?: "align" | "one-space";
+  trimTrailingWhitespace?: boolean;
+  tabWidth?: number | null;
+  preserveTabs?: boolean;
+  maxBlankLines?: number | null;
+  finalNewline?: boolean;
+  explain?: boolean;
+  document?: "markdown" | "org" | null;
+}
+
+export type ParinferErrorName =
+  | "quote-danger"
+  | "eol-backslash"
+  | "unclosed-quote"
+  | "unclosed-paren"
+  | "unmatched-close-paren"
+  | "unmatched-open-paren"
+  | "leading-close-paren"
+  | "utf8-error"
+  | "json-error"
+  | "panic";
+
+export interface ParinferErrorExtra {
+  name: ParinferErrorName;
+  x: number;
+  lineNo: number;
+  inputX: number;
+  inputLineNo: number;
+}
+
+export interface ParinferError {
+  name: ParinferErrorName;
+  message: string;
+  x: number;
+  lineNo: number;
+  inputX: number;
+  inputLineNo: number;
+  extra?: ParinferErrorExtra;
+}
+
+export interface ParinferTabStop {
+  ch: string;
+  x: number;
+  lineNo: number;
+  argX: number | null;
+}
+
+export interface ParinferParenTrail {
+  lineNo: number;
+  startX: number;
+  endX: number;
+}
+
+export interface ParinferParen {
+  lineNo: number;
+  ch: string;
+  x: number;
+  indentDelta: number;
+  maxChildIndent: number | null;
+  argX: number | null;
+  inputLineNo: number;
+  inputX: number;
+}
+
+export type ParinferDecisionName =
+  | "paren-trail-moved"
+  | "paren-trail-held"
+  | "close-paren-removed"
+  | "close-paren-moved"
+  | "close-paren-kept"
+  | "indent-shifted"
+  | "indent-clamped";
+
+export interface ParinferDecision {
+  name: ParinferDecisionName;
+  message: string;
+  x: number;
+  lineNo: number;
+}
+
+export interface ParinferResult {
+  text: string;
+  success: boolean;
+  error: ParinferError | null;
+  cursorX: number | null;
+  cursorLine: number | null;
+  tabStops: ParinferTabStop[];
+  parenTrails: ParinferParenTrail[];
+  parens: ParinferParen[];
+  /** With isolateForms or a document, the errors of the parts left untouched. */
+  errors?: ParinferError[];
+  positions?: ParinferPosition[];
+  trace?: ParinferDecision[];
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "ParinferOptions")]
+    pub type JsOptions;
+
+    #[wasm_bindgen(typescript_type = "ParinferResult")]
+    pub type JsAnswer;
+}
+
+// Serialize `None` as `null` and maps as plain objects, like JSON.parse would.
+const SERIALIZER: Serializer = Serializer::json_compatible();
+
+fn parse_options(options: Option<JsOptions>) -> Result<Options, Error> {
+    match options {
+        Some(options) if !options.is_undefined() && !options.is_null() => {
+            Ok(serde_wasm_bindgen::from_value(options.into())?)
+        }
+        _ => Ok(serde_json::from_str("{}")?),
+    }
+}
+
+fn internal_run(mode: &str, text: String, options: Option<JsOptions>) -> Result<JsValue, Error> {
+    let request = Request {
+        mode: String::from(mode),
+        text,
+        options: parse_options(options)?,
+    };
+    let answer = parinfer::process(&request);
+    Ok(answer.serialize(&SERIALIZER)?)
+}
+
+fn run(mode: &str, text: String, options: Option<JsOptions>) -> JsAnswer {
+    // There is nowhere to write a crash file in the browser.
+    common_wrapper::catch_panic(None, || internal_run(mode, text, options))
+        .and_then(|result| result)
+        .unwrap_or_else(|e| Answer::from(e).serialize(&SERIALIZER).unwrap())
+        .unchecked_into()
+}
+
+/// Runs indent mode, taking and returning structured objects like parinfer.js.
+#[wasm_bindgen(js_name = indentMode)]
+pub fn indent_mode(text: String, options: Option<JsOptions>) -> JsAnswer {
+    run("indent", text, options)
+}
+
+/// Runs paren mode, taking and returning structured objects like parinfer.js.
+#[wasm_bindgen(js_name = parenMode)]
+pub fn paren_mode(text: String, options: Option<JsOptions>) -> JsAnswer {
+    run("paren", text, options)
+}
+
+/// Runs smart mode, taking and returning structured objects like parinfer.js.
+#[wasm_bindgen(js_name = smartMode)]
+pub fn smart_mode(text: String, options: Option<JsOptions>) -> JsAnswer {
+    run("smart", text, options)
+}
+
+/// The JSON-string interface, kept for existing callers.
+#[wasm_bindgen]
 pub fn run_parinfer(input: String) -> String {
-    match panic::catch_unwind(|| common_wrapper::internal_run(&input)) {
+    match common_wrapper::catch_panic(Some(&input), || common_wrapper::internal_run(&input)) {
         Ok(Ok(result)) => result,
-        Ok(Err(e)) => serde_json::to_string(&Answer::from(e)).unwrap(),
-        Err(_) => common_wrapper::panic_result(),
+        Ok(Err(e)) | Err(e) => serde_json::to_string(&Answer::from(e)).unwrap(),
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::run_parinfer;
-    use serde_json;
+    use super::*;
     use serde_json::Value;
+    use wasm_bindgen_test::*;
 
     #[test]
     fn it_works() {
@@ -41,4 +234,52 @@ mod tests {
             "returns correct text"
         );
     }
+
+    fn to_json(answer: JsAnswer) -> Value {
+        serde_wasm_bindgen::from_value(answer.into()).unwrap()
+    }
+
+    fn options(json: Value) -> Option<JsOptions> {
+        Some(json.serialize(&SERIALIZER).unwrap().unchecked_into())
+    }
+
+    #[wasm_bindgen_test]
+    fn indent_mode_returns_a_structured_result() {
+        let answer = to_json(indent_mode(
+            String::from("(def x"),
+            options(serde_json::json!({"cursorX": 3, "cursorLine": 0})),
+        ));
+        assert_eq!(Value::Bool(true), answer["success"]);
+        assert_eq!(Value::String(String::from("(def x)")), answer["text"]);
+        assert_eq!(Value::Null, answer["error"]);
+        assert_eq!(serde_json::json!(3), answer["cursorX"]);
+    }
+
+    #[wasm_bindgen_test]
+    fn options_are_optional() {
+        let answer = to_json(paren_mode(String::from("(foo\nbar)"), None));
+        assert_eq!(Value::String(String::from("(foo\n bar)")), answer["text"]);
+    }
+
+    #[wasm_bindgen_test]
+    fn errors_are_structured() {
+        let answer = to_json(paren_mode(String::from("(foo"), None));
+        assert_eq!(Value::Bool(false), answer["success"]);
+        assert_eq!(
+            Value::String(String::from("unclosed-paren")),
+            answer["error"]["name"]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn bad_options_are_reported() {
+        let answer = to_json(smart_mode(
+            String::from("(foo"),
+            options(serde_json::json!({"cursorX": "nope"})),
+        ));
+        assert_eq!(
+            Value::String(String::from("json-error")),
+            answer["error"]["name"]
+        );
+    }
 }
@@ -678,3 +678,205 @@ pub fn hy_bracket_strings() {
         case.check2(answer);
     }
 }
+
+#[test]
+pub fn unmatched_close_paren_error_locates_its_opener() {
+    for &mode in &["paren", "indent"] {
+        let input = json!({
+            "mode": mode,
+            "text": "(foo [bar) baz",
+            "options": {}
+        })
+        .to_string();
+        let answer: serde_json::Value = serde_json::from_str(&run(&input)).unwrap();
+        assert_eq!(json!("unmatched-close-paren"), answer["error"]["name"], "{}", mode);
+        assert_eq!(json!(9), answer["error"]["x"], "{}", mode);
+        assert_eq!(
+            json!({
+                "name": "unmatched-open-paren",
+                "lineNo": 0,
+                "x": 5,
+                "inputLineNo": 0,
+                "inputX": 5
+            }),
+            answer["error"]["extra"],
+            "{}",
+            mode
+        );
+    }
+}
+
+#[test]
+pub fn unclosed_paren_error_locates_the_missing_closer() {
+    let input = json!({
+        "mode": "paren",
+        "text": "(foo\n  bar",
+        "options": {}
+    })
+    .to_string();
+    let answer: serde_json::Value = serde_json::from_str(&run(&input)).unwrap();
+    assert_eq!(json!("unclosed-paren"), answer["error"]["name"]);
+    assert_eq!(json!(0), answer["error"]["lineNo"]);
+    assert_eq!(json!(0), answer["error"]["x"]);
+    assert_eq!(json!(1), answer["error"]["extra"]["lineNo"]);
+    assert_eq!(json!(5), answer["error"]["extra"]["x"]);
+}
+
+#[test]
+pub fn other_errors_have_no_extra() {
+    let input = json!({
+        "mode": "indent",
+        "text": "(foo \"bar",
+        "options": {}
+    })
+    .to_string();
+    let answer: serde_json::Value = serde_json::from_str(&run(&input)).unwrap();
+    assert_eq!(json!("unclosed-quote"), answer["error"]["name"]);
+    assert!(answer["error"].get("extra").is_none());
+}
+
+#[test]
+pub fn analyze_mode_reports_every_error() {
+    let input = json!({
+        "mode": "analyze",
+        "text": "(foo))\n(bar\n\n(baz \"qux)",
+        "options": {}
+    })
+    .to_string();
+    let answer: serde_json::Value = serde_json::from_str(&run(&input)).unwrap();
+    assert_eq!(json!(false), answer["success"]);
+    assert_eq!(json!("(foo))\n(bar\n\n(baz \"qux)"), answer["text"]);
+    let names: Vec<&serde_json::Value> = answer["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| &e["name"])
+        .collect();
+    assert_eq!(
+        vec![
+            &json!("unmatched-close-paren"),
+            &json!("unclosed-paren"),
+            &json!("unclosed-quote")
+        ],
+        names
+    );
+    assert_eq!(answer["errors"][0], answer["error"]);
+}
+
+#[test]
+pub fn report_all_errors_option() {
+    let input = json!({
+        "mode": "indent",
+        "text": "(foo \"bar)\n(baz]",
+        "options": { "reportAllErrors": true }
+    })
+    .to_string();
+    let answer: serde_json::Value = serde_json::from_str(&run(&input)).unwrap();
+    assert_eq!(json!("unclosed-quote"), answer["error"]["name"]);
+    assert_eq!(2, answer["errors"].as_array().unwrap().len());
+
+    let input = json!({
+        "mode": "indent",
+        "text": "(foo \"bar)\n(baz]",
+        "options": {}
+    })
+    .to_string();
+    let answer: serde_json::Value = serde_json::from_str(&run(&input)).unwrap();
+    assert!(answer.get("errors").is_none());
+}
+
+#[test]
+pub fn isolate_forms_option_leaves_only_the_broken_form_untouched() {
+    let text = "(def a \"unclosed)\n\n(defn b [x]\n  (+ x 1";
+    let input = json!({
+        "mode": "smart",
+        "text": text,
+        "options": {
+            "cursorX": 9,
+            "cursorLine": 3,
+            "isolateForms": true
+        }
+    })
+    .to_string();
+    let answer: serde_json::Value = serde_json::from_str(&run(&input)).unwrap();
+    assert_eq!(json!(false), answer["success"]);
+    assert_eq!(json!("unclosed-quote"), answer["error"]["name"]);
+    assert_eq!(
+        json!("(def a \"unclosed)\n\n(defn b [x]\n  (+ x 1))"),
+        answer["text"]
+    );
+    assert_eq!(json!(3), answer["cursorLine"]);
+    assert_eq!(json!(9), answer["cursorX"]);
+    assert_eq!(json!("unclosed-quote"), answer["errors"][0]["name"]);
+    assert_eq!(json!(0), answer["errors"][0]["lineNo"]);
+    assert_eq!(json!(7), answer["errors"][0]["x"]);
+    assert_eq!(json!(2), answer["tabStops"][0]["lineNo"]);
+    assert_eq!(json!(2), answer["parenTrails"][0]["lineNo"]);
+
+    let input = json!({
+        "mode": "smart",
+        "text": text,
+        "options": {}
+    })
+    .to_string();
+    let answer: serde_json::Value = serde_json::from_str(&run(&input)).unwrap();
+    assert_eq!(json!(false), answer["success"]);
+    assert_eq!(json!(text), answer["text"]);
+}
+
+#[derive(Deserialize)]
+struct AnnotatedCase {
+    text: String,
+    options: serde_json::Value,
+    result: serde_json::Value,
+    source: AnnotatedSource,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AnnotatedSource {
+    line_no: LineNumber,
+    #[serde(rename = "in")]
+    input: Vec<String>,
+    out: String,
+}
+
+#[test]
+pub fn annotated_notation_agrees_with_every_case() {
+    use parinfer_rust::annotated;
+
+    for cases in &[INDENT_MODE_CASES, PAREN_MODE_CASES, SMART_MODE_CASES] {
+        let cases: Vec<AnnotatedCase> = serde_json::from_str(cases).unwrap();
+        for case in cases {
+            let line_no = case.source.line_no;
+            if case.source.input.len() == 1 {
+                let input = annotated::parse(&case.source.input[0]).unwrap();
+                assert_eq!(case.text, input.text, "case {}: text", line_no);
+                assert_eq!(json!(input.cursor_x), case.options["cursorX"], "case {}: cursorX", line_no);
+                assert_eq!(json!(input.cursor_line), case.options["cursorLine"], "case {}: cursorLine", line_no);
+                assert_eq!(json!(input.prev_cursor_x), case.options["prevCursorX"], "case {}: prevCursorX", line_no);
+                let changes = input
+                    .changes
+                    .iter()
+                    .map(|c| json!({"lineNo": c.line_no, "x": c.x, "oldText": c.old_text, "newText": c.new_text}))
+                    .collect::<Vec<_>>();
+                let expected = case.options.get("changes").cloned().unwrap_or(json!([]));
+                assert_eq!(json!(changes), expected, "case {}: changes", line_no);
+                assert_eq!(annotated::print(&input), case.source.input[0], "case {}: printed input", line_no);
+            }
+
+            let output = annotated::parse(&case.source.out).unwrap();
+            assert_eq!(json!(output.text), case.result["text"], "case {}: result text", line_no);
+            assert_eq!(json!(output.cursor_x), case.result["cursorX"], "case {}: result cursorX", line_no);
+            assert_eq!(
+                json!(output.error.as_ref().map(|e| json!({"name": e.name, "lineNo": e.line_no, "x": e.x}))),
+                case.result["error"],
+                "case {}: result error",
+                line_no
+            );
+            if !case.source.out.contains("parenTrail") && !case.source.out.contains("tabStops") {
+                assert_eq!(annotated::print(&output), case.source.out, "case {}: printed output", line_no);
+            }
+        }
+    }
+}